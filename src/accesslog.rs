@@ -0,0 +1,170 @@
+//! The access log, which records one line for each query served
+use {
+    crate::{
+        config::{AccessLog, Destination, LogFormat},
+        log::error,
+        syslog::{Severity, Syslog},
+    },
+    std::{
+        fmt::{self, Write as _},
+        fs::{File, OpenOptions},
+        io::{self, Error, Write},
+        net::{IpAddr, SocketAddr},
+        sync::{Mutex, OnceLock},
+        time::Duration,
+    },
+};
+
+static LOG: OnceLock<Log> = OnceLock::new();
+
+struct Log {
+    format: LogFormat,
+    sink: Mutex<Sink>,
+}
+
+enum Sink {
+    Stdout,
+    File(File),
+    Syslog(Syslog),
+}
+
+/// The result of a single query
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The system info was served
+    Info,
+    /// A user's plan was served
+    User,
+    /// The requested user does not exist
+    Unknown,
+    /// The query was forwarded to another host
+    Forwarded,
+    /// Forwarding the query was refused
+    Refused,
+    /// The client is not permitted to make this query
+    Denied,
+    /// The query could not be parsed
+    Malformed,
+    /// The query was longer than the maximum allowed
+    TooLong,
+    /// The client was too slow in sending the query or reading the response
+    TimedOut,
+    /// Some other error occurred
+    Error,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Info => "info",
+            Self::User => "user",
+            Self::Unknown => "unknown",
+            Self::Forwarded => "forwarded",
+            Self::Refused => "refused",
+            Self::Denied => "denied",
+            Self::Malformed => "malformed",
+            Self::TooLong => "too-long",
+            Self::TimedOut => "timeout",
+            Self::Error => "error",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// A single line in the access log
+pub struct Entry {
+    /// The address of the listener which the connection arrived on
+    pub listener: SocketAddr,
+    pub peer: IpAddr,
+    pub query: String,
+    pub outcome: Outcome,
+    /// The number of bytes sent in the response
+    pub bytes: usize,
+    /// The time taken to serve the connection
+    pub latency: Duration,
+}
+
+/// Opens the configured log destination. This must be done before entering
+/// the chroot.
+pub fn open(cfg: &AccessLog) -> Result<(), Error> {
+    if !cfg.enabled {
+        return Ok(());
+    }
+    let sink = match cfg.destination {
+        Destination::Stdout => Sink::Stdout,
+        Destination::File => Sink::File(
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&cfg.path)
+                .map_err(|e| Error::new(e.kind(), format!("{}: {e}", cfg.path)))?,
+        ),
+        Destination::Syslog => Sink::Syslog(Syslog::connect()?),
+    };
+    _ = LOG.set(Log {
+        format: cfg.format,
+        sink: Mutex::new(sink),
+    });
+    Ok(())
+}
+
+/// Escapes a string for use inside of double quotes in either format
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                _ = write!(out, "\\u{:04x}", u32::from(c));
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+impl Entry {
+    fn format(&self, format: LogFormat) -> String {
+        let now = chrono::Utc::now();
+        let query = escape(&self.query);
+        let latency = self.latency.as_millis();
+        match format {
+            LogFormat::Common => format!(
+                "{} - - [{}] \"{query}\" {} {} {latency}ms {}",
+                self.peer,
+                now.format("%d/%b/%Y:%H:%M:%S %z"),
+                self.outcome,
+                self.bytes,
+                self.listener,
+            ),
+            LogFormat::Json => format!(
+                "{{\"time\":\"{}\",\"listener\":\"{}\",\"peer\":\"{}\",\"query\":\"{query}\",\"outcome\":\"{}\",\"bytes\":{},\"latency_ms\":{latency}}}",
+                now.to_rfc3339(),
+                self.listener,
+                self.peer,
+                self.outcome,
+                self.bytes,
+            ),
+        }
+    }
+}
+
+/// Writes an entry to the access log, if it is enabled
+pub fn log(entry: &Entry) {
+    let Some(log) = LOG.get() else {
+        return;
+    };
+    let line = entry.format(log.format);
+    let Ok(mut sink) = log.sink.lock() else {
+        return;
+    };
+    let res = match &mut *sink {
+        Sink::Stdout => writeln!(io::stdout(), "{line}"),
+        Sink::File(file) => writeln!(file, "{line}"),
+        Sink::Syslog(syslog) => syslog.send(Severity::Info, &line),
+    };
+    if let Err(e) = res {
+        error!("Unable to write to access log: {e}");
+    }
+}
@@ -0,0 +1,122 @@
+//! Receiving already bound or connected sockets from a service manager, either
+//! through systemd's `LISTEN_FDS` protocol or on stdin in the manner of inetd
+use std::{
+    env,
+    fs::File,
+    io::Error,
+    mem,
+    net::{TcpListener, TcpStream},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    process,
+};
+
+/// The first file descriptor passed by systemd
+const LISTEN_FDS_START: RawFd = 3;
+
+fn fstat(fd: RawFd) -> Result<libc::stat, Error> {
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    if unsafe { libc::fstat(fd, &raw mut stat) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(stat)
+}
+
+fn is_socket(fd: RawFd) -> Result<bool, Error> {
+    Ok(fstat(fd)?.st_mode & libc::S_IFMT == libc::S_IFSOCK)
+}
+
+/// Reads an integer socket option
+fn sockopt(fd: RawFd, option: libc::c_int) -> Result<libc::c_int, Error> {
+    let mut value: libc::c_int = 0;
+    let mut len = libc::socklen_t::try_from(mem::size_of::<libc::c_int>()).unwrap_or(0);
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            (&raw mut value).cast(),
+            &raw mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(value)
+}
+
+/// Checks that a passed file descriptor is a listening TCP socket, which is
+/// the only kind that can be used as a `TcpListener`
+fn check_listener(fd: RawFd) -> Result<(), Error> {
+    let problem = if !is_socket(fd)? {
+        "is not a socket"
+    } else if !matches!(
+        sockopt(fd, libc::SO_DOMAIN)?,
+        libc::AF_INET | libc::AF_INET6
+    ) {
+        "is not an IPv4 or IPv6 socket"
+    } else if sockopt(fd, libc::SO_TYPE)? != libc::SOCK_STREAM {
+        "is not a stream socket"
+    } else if sockopt(fd, libc::SO_ACCEPTCONN)? == 0 {
+        "is not listening"
+    } else {
+        return Ok(());
+    };
+    Err(Error::other(format!(
+        "passed file descriptor {fd} {problem}"
+    )))
+}
+
+/// Takes the listening sockets passed by systemd, if any. The environment
+/// variables are removed so that they are not inherited by child processes.
+/// This must be called before any other threads are started.
+pub fn listeners() -> Result<Option<Vec<TcpListener>>, Error> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    let (Some(pid), Some(fds)) = (pid, fds) else {
+        return Ok(None);
+    };
+    if pid.parse::<u32>().ok() != Some(process::id()) {
+        return Ok(None);
+    }
+    let fds = fds
+        .parse::<RawFd>()
+        .map_err(|e| Error::other(format!("invalid LISTEN_FDS `{fds}`: {e}")))?;
+    let mut listeners = vec![];
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + fds {
+        check_listener(fd)?;
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        listeners.push(unsafe { TcpListener::from_raw_fd(fd) });
+    }
+    Ok(Some(listeners))
+}
+
+/// Takes the connected socket passed on stdin by inetd or by a systemd socket
+/// with `Accept=yes`. The socket is moved off of the standard file descriptors,
+/// and any of stdin, stdout and stderr which refer to it are pointed at
+/// `/dev/null` so that log messages are never written to the client.
+pub fn stdin() -> Result<TcpStream, Error> {
+    let conn = fstat(libc::STDIN_FILENO)?;
+    if conn.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+        return Err(Error::other("stdin is not a socket"));
+    }
+    let fd = unsafe { libc::fcntl(libc::STDIN_FILENO, libc::F_DUPFD_CLOEXEC, 3) };
+    if fd < 0 {
+        return Err(Error::last_os_error());
+    }
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+    // Make sure that this is a TCP socket before trying to serve it
+    stream.peer_addr()?;
+    let null = File::options().read(true).write(true).open("/dev/null")?;
+    for std in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        let same = fstat(std).is_ok_and(|s| s.st_dev == conn.st_dev && s.st_ino == conn.st_ino);
+        if same && unsafe { libc::dup2(null.as_raw_fd(), std) } < 0 {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(stream)
+}
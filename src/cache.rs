@@ -0,0 +1,287 @@
+//! An in-memory cache of the files served for each user, keyed by path. An
+//! entry which was checked against the file's metadata within the last
+//! `cache.ttl` seconds is served without touching the disk. Older entries are
+//! checked again, so a changed file is read again soon after it changes.
+use {
+    crate::{config::Config, log::info, username::Username},
+    std::{
+        collections::{BTreeMap, HashMap},
+        fs::{self, File},
+        io::{Error, Read},
+        os::unix::fs::MetadataExt,
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, LazyLock, Mutex,
+        },
+        time::{Duration, Instant, SystemTime},
+    },
+};
+
+static CACHE: LazyLock<Mutex<Entries>> = LazyLock::new(|| Mutex::new(Entries::default()));
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies a version of a file. Any change to the file's contents updates
+/// at least one of these.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    dev: u64,
+    ino: u64,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl From<&fs::Metadata> for Stamp {
+    fn from(meta: &fs::Metadata) -> Self {
+        Self {
+            dev: meta.dev(),
+            ino: meta.ino(),
+            len: meta.len(),
+            modified: meta.modified().ok(),
+        }
+    }
+}
+
+struct Entry {
+    stamp: Stamp,
+    contents: Arc<[u8]>,
+    /// When the entry was last used, for evicting the least recently used
+    used: u64,
+    /// When the entry was last checked against the file
+    checked: Instant,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<PathBuf, Entry>,
+    /// The path of each entry by when it was last used, oldest first
+    lru: BTreeMap<u64, PathBuf>,
+    /// The total size of the cached contents
+    size: usize,
+    tick: u64,
+}
+
+impl Entries {
+    /// Marks an entry as the most recently used
+    fn touch(&mut self, path: &Path) -> Option<Arc<[u8]>> {
+        self.tick += 1;
+        let entry = self.map.get_mut(path)?;
+        if let Some(path) = self.lru.remove(&entry.used) {
+            self.lru.insert(self.tick, path);
+        }
+        entry.used = self.tick;
+        Some(Arc::clone(&entry.contents))
+    }
+
+    /// An entry which was checked against the file less than `ttl` ago
+    fn fresh(&mut self, path: &Path, ttl: Duration) -> Option<Arc<[u8]>> {
+        if self.map.get(path)?.checked.elapsed() >= ttl {
+            return None;
+        }
+        self.touch(path)
+    }
+
+    /// An entry which matches the file as it is now
+    fn revalidate(&mut self, path: &Path, stamp: Stamp) -> Option<Arc<[u8]>> {
+        let entry = self.map.get_mut(path)?;
+        if entry.stamp != stamp {
+            return None;
+        }
+        entry.checked = Instant::now();
+        self.touch(path)
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.map.remove(path) {
+            self.size -= entry.contents.len();
+            self.lru.remove(&entry.used);
+        }
+    }
+
+    fn insert(&mut self, path: PathBuf, stamp: Stamp, contents: Arc<[u8]>, max: usize) {
+        self.remove(&path);
+        if contents.len() > max {
+            return;
+        }
+        while self.size + contents.len() > max {
+            let Some((_, oldest)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.map.remove(&oldest) {
+                self.size -= entry.contents.len();
+            }
+        }
+        self.tick += 1;
+        self.size += contents.len();
+        self.lru.insert(self.tick, path.clone());
+        self.map.insert(
+            path,
+            Entry {
+                stamp,
+                contents,
+                used: self.tick,
+                checked: Instant::now(),
+            },
+        );
+    }
+}
+
+/// Reads up to one byte more than `content.max_size` from `opened`, so that
+/// the caller can tell whether the file was cut short
+fn read_limited(cfg: &Config, opened: &File) -> Result<Arc<[u8]>, Error> {
+    let limit = u64::try_from(cfg.content.max_size).unwrap_or(u64::MAX);
+    let mut contents = vec![];
+    opened
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut contents)?;
+    Ok(Arc::from(contents))
+}
+
+/// Reads `file` from the directory of `user` in `root`, from the cache where
+/// possible. Returns `None` if the file cannot be served.
+pub fn read(
+    cfg: &Config,
+    user: &Username,
+    file: &str,
+    root: &Path,
+) -> Result<Option<Arc<[u8]>>, Error> {
+    if !cfg.cache.enabled {
+        return user
+            .open(root, file, cfg.symlinks)?
+            .map(|opened| read_limited(cfg, &opened))
+            .transpose();
+    }
+    let path = root.join(user.as_ref()).join(file);
+    let ttl = Duration::from_secs(cfg.cache.ttl);
+    if let Some(contents) = CACHE.lock().unwrap().fresh(&path, ttl) {
+        HITS.fetch_add(1, Ordering::Relaxed);
+        return Ok(Some(contents));
+    }
+    let Some(opened) = user.open(root, file, cfg.symlinks)? else {
+        CACHE.lock().unwrap().remove(&path);
+        return Ok(None);
+    };
+    let stamp = Stamp::from(&opened.metadata()?);
+    if let Some(contents) = CACHE.lock().unwrap().revalidate(&path, stamp) {
+        HITS.fetch_add(1, Ordering::Relaxed);
+        return Ok(Some(contents));
+    }
+    MISSES.fetch_add(1, Ordering::Relaxed);
+    // The metadata was read first, so the contents are at least as new as the
+    // stamp, and any later change is noticed by the next check. A file which
+    // is replaced rather than edited gets a new inode, which is also noticed.
+    let contents = read_limited(cfg, &opened)?;
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(path, stamp, Arc::clone(&contents), cfg.cache.max_size);
+    Ok(Some(contents))
+}
+
+/// Empties the cache, such as when it has been disabled or its limits changed
+pub fn clear() {
+    let mut entries = CACHE.lock().unwrap();
+    entries.map.clear();
+    entries.lru.clear();
+    entries.size = 0;
+}
+
+/// Logs the number of reads which were and were not served from the cache,
+/// and how much it holds
+pub fn report() {
+    let hits = HITS.load(Ordering::Relaxed);
+    let misses = MISSES.load(Ordering::Relaxed);
+    let entries = CACHE.lock().unwrap();
+    info!(
+        "Plan cache: {hits} hits, {misses} misses, {} files in {} bytes.",
+        entries.map.len(),
+        entries.size
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp(len: u64) -> Stamp {
+        Stamp {
+            dev: 1,
+            ino: 1,
+            len,
+            modified: None,
+        }
+    }
+
+    fn insert(entries: &mut Entries, path: &str, len: usize, max: usize) {
+        let contents = Arc::from(vec![b'x'; len]);
+        entries.insert(PathBuf::from(path), stamp(len as u64), contents, max);
+    }
+
+    fn cached(entries: &Entries) -> Vec<&str> {
+        let mut paths = entries
+            .map
+            .keys()
+            .filter_map(|p| p.to_str())
+            .collect::<Vec<_>>();
+        paths.sort_unstable();
+        paths
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut entries = Entries::default();
+        insert(&mut entries, "a", 4, 10);
+        insert(&mut entries, "b", 4, 10);
+        // Using `a` leaves `b` as the oldest
+        assert!(entries.touch(Path::new("a")).is_some());
+        insert(&mut entries, "c", 4, 10);
+        assert_eq!(cached(&entries), ["a", "c"]);
+        assert_eq!(entries.size, 8);
+        // Room is made for a large entry by evicting as many as needed
+        insert(&mut entries, "d", 10, 10);
+        assert_eq!(cached(&entries), ["d"]);
+        assert_eq!(entries.size, 10);
+        assert_eq!(entries.lru.len(), 1);
+    }
+
+    #[test]
+    fn too_large() {
+        let mut entries = Entries::default();
+        insert(&mut entries, "a", 4, 10);
+        insert(&mut entries, "b", 11, 10);
+        assert_eq!(cached(&entries), ["a"]);
+        // A file which has grown too large is no longer cached
+        insert(&mut entries, "a", 11, 10);
+        assert!(entries.map.is_empty());
+        assert!(entries.lru.is_empty());
+        assert_eq!(entries.size, 0);
+    }
+
+    #[test]
+    fn replaced() {
+        let mut entries = Entries::default();
+        insert(&mut entries, "a", 4, 10);
+        insert(&mut entries, "a", 6, 10);
+        assert_eq!(entries.size, 6);
+        assert_eq!(entries.lru.len(), 1);
+        assert!(entries.revalidate(Path::new("a"), stamp(4)).is_none());
+        assert!(entries.revalidate(Path::new("a"), stamp(6)).is_some());
+        entries.remove(Path::new("a"));
+        assert_eq!(entries.size, 0);
+        assert!(entries.lru.is_empty());
+    }
+
+    #[test]
+    fn fresh() {
+        let mut entries = Entries::default();
+        insert(&mut entries, "a", 4, 10);
+        assert!(entries
+            .fresh(Path::new("a"), Duration::from_secs(60))
+            .is_some());
+        assert!(entries.fresh(Path::new("a"), Duration::ZERO).is_none());
+        assert!(entries
+            .fresh(Path::new("b"), Duration::from_secs(60))
+            .is_none());
+    }
+}
@@ -0,0 +1,134 @@
+use {
+    crate::{config::Config, log::warning},
+    std::{env, net::SocketAddr, path::PathBuf, process},
+};
+
+const USAGE: &str = "Usage: toe [OPTIONS]
+
+Options:
+  -c, --config <PATH>    Read the config from PATH [default: /etc/toe.toml]
+      --check            Validate the config and exit
+      --print-config     Print the effective config and exit
+  -f, --foreground       Allow starting without root, for testing. When started
+                         as root, toe still chroots and drops privileges
+  -i, --inetd            Serve a single query on stdin and exit
+  -a, --address <ADDR>   Listen only on ADDR, ignoring the `listen` setting
+  -p, --port <PORT>      Override the port to listen on
+  -h, --help             Print this help and exit
+  -V, --version          Print the version and exit";
+
+/// Options given on the command line
+#[allow(clippy::struct_excessive_bools)]
+pub struct Args {
+    /// The path to the config file
+    pub config: PathBuf,
+    /// Validate the config and exit
+    pub check: bool,
+    /// Print the effective config and exit
+    pub print_config: bool,
+    /// Allow running without root, rather than requiring socket activation
+    pub foreground: bool,
+    /// Serve a single connection passed on stdin
+    pub inetd: bool,
+    /// Whether toe was started by the root user
+    pub privileged: bool,
+    /// Overrides `Config::address`
+    pub address: Option<String>,
+    /// Overrides `Config::port`
+    pub port: Option<String>,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            config: PathBuf::from("/etc/toe.toml"),
+            check: false,
+            print_config: false,
+            foreground: false,
+            inetd: false,
+            privileged: false,
+            address: None,
+            port: None,
+        }
+    }
+}
+
+impl Args {
+    /// Parses the process arguments, exiting on error or after printing the
+    /// help or version text
+    pub fn parse() -> Self {
+        match Self::from_iter(env::args().skip(1)) {
+            Ok(args) => args,
+            Err(e) => {
+                eprintln!("toe: {e}\n\n{USAGE}");
+                process::exit(1);
+            }
+        }
+    }
+
+    fn from_iter<I: Iterator<Item = String>>(mut iter: I) -> Result<Self, String> {
+        let mut args = Self {
+            privileged: unsafe { libc::getuid() } == 0,
+            ..Self::default()
+        };
+        while let Some(arg) = iter.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((f, v)) if f.starts_with("--") => (f.to_string(), Some(v.to_string())),
+                _ => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| iter.next())
+                    .ok_or_else(|| format!("option '{flag}' requires a value"))
+            };
+            match flag.as_str() {
+                "-c" | "--config" => args.config = PathBuf::from(value()?),
+                "--check" => args.check = true,
+                "--print-config" => args.print_config = true,
+                "-f" | "--foreground" => args.foreground = true,
+                "-i" | "--inetd" => args.inetd = true,
+                "-a" | "--address" => args.address = Some(value()?),
+                "-p" | "--port" => args.port = Some(value()?),
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    process::exit(0);
+                }
+                "-V" | "--version" => {
+                    println!("toe {}", env!("CARGO_PKG_VERSION"));
+                    process::exit(0);
+                }
+                _ => return Err(format!("unrecognized option '{flag}'")),
+            }
+        }
+        Ok(args)
+    }
+
+    /// Applies the command line overrides to a loaded config
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(address) = &self.address {
+            config.address.clone_from(address);
+            config.listen.clear();
+        }
+        if let Some(port) = &self.port {
+            config.port.clone_from(port);
+            if let Ok(port) = port.parse::<u16>() {
+                for listen in &mut config.listen {
+                    if let Ok(mut addr) = listen.parse::<SocketAddr>() {
+                        addr.set_port(port);
+                        *listen = addr.to_string();
+                    }
+                }
+            }
+        }
+        // Without root we can neither chroot nor drop privileges, so the
+        // server root is served directly
+        if !self.privileged && config.chroot {
+            warning!(
+                "`chroot` is set, but toe was not started as root, so {} is served without a chroot.",
+                config.root
+            );
+            config.chroot = false;
+        }
+    }
+}
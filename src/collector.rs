@@ -0,0 +1,238 @@
+//! A background thread which refreshes the system stats every
+//! `stats_interval` seconds and publishes them as an immutable snapshot. Only
+//! the parts of the system which are needed by the configured stats are
+//! scanned, and queries only ever read the latest snapshot, so no client can
+//! cause a scan or be held up waiting for one.
+use {
+    crate::config::{Config, Stats},
+    std::{
+        collections::HashMap,
+        sync::{Arc, LazyLock, RwLock},
+        thread,
+        time::{Duration, Instant},
+    },
+    sysinfo::{ComponentExt, DiskExt, NetworkExt, System, SystemExt},
+};
+
+static SNAPSHOT: LazyLock<RwLock<Arc<Snapshot>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(Snapshot::default())));
+
+/// The state of the system at a single point in time
+#[derive(Default)]
+pub struct Snapshot {
+    /// When the snapshot was taken
+    pub taken: Option<Instant>,
+    pub os_name: Option<String>,
+    pub kernel_version: Option<String>,
+    pub os_version: Option<String>,
+    /// Seconds since boot, as of when the snapshot was taken
+    pub uptime: u64,
+    /// The number of users known to the system
+    pub users: usize,
+    pub load: (f64, f64, f64),
+    pub memory: Memory,
+    pub disks: Vec<Disk>,
+    pub interfaces: Vec<Interface>,
+    pub sensors: Vec<Sensor>,
+}
+
+/// Memory and swap, in bytes
+#[derive(Default)]
+pub struct Memory {
+    pub total: u64,
+    pub used: u64,
+    pub available: u64,
+    pub swap_total: u64,
+    pub swap_used: u64,
+    pub swap_free: u64,
+}
+
+pub struct Disk {
+    pub mount: String,
+    pub file_system: String,
+    pub total: u64,
+    pub available: u64,
+}
+
+pub struct Interface {
+    pub name: String,
+    /// Bytes received since boot
+    pub received: u64,
+    /// Bytes transmitted since boot
+    pub transmitted: u64,
+    /// Bytes received per second since the previous snapshot
+    pub rx_rate: Option<u64>,
+    /// Bytes transmitted per second since the previous snapshot
+    pub tx_rate: Option<u64>,
+}
+
+/// A single temperature sensor, in degrees Celsius
+#[derive(Clone)]
+pub struct Sensor {
+    pub label: String,
+    pub temperature: f32,
+    pub max: Option<f32>,
+    pub critical: Option<f32>,
+}
+
+impl Snapshot {
+    /// Seconds since boot, as of now
+    pub fn uptime(&self) -> u64 {
+        self.uptime + self.taken.map_or(0, |t| t.elapsed().as_secs())
+    }
+}
+
+/// Returns the latest snapshot
+pub fn snapshot() -> Arc<Snapshot> {
+    Arc::clone(&SNAPSHOT.read().unwrap())
+}
+
+/// The stats which are shown, either by the `stats` list or by the template
+fn needed(cfg: &Config) -> Vec<Stats> {
+    let mut stats = cfg.stats.clone();
+    if let Some(layout) = &cfg.layout {
+        stats.extend(layout.stats());
+    }
+    stats
+}
+
+/// Bytes per second between two readings of a counter
+fn rate(now: u64, then: u64, elapsed: Duration) -> Option<u64> {
+    let secs = elapsed.as_secs_f64();
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    (secs > 0.0).then(|| (now.saturating_sub(then) as f64 / secs) as u64)
+}
+
+struct Collector {
+    sys: System,
+    /// The totals seen for each interface by the previous scan, and when
+    traffic: HashMap<String, (u64, u64, Instant)>,
+}
+
+impl Collector {
+    fn collect(&mut self, cfg: &Config) -> Snapshot {
+        let needed = needed(cfg);
+        let needs = |stats: &[Stats]| stats.iter().any(|s| needed.contains(s));
+        let sys = &mut self.sys;
+        let now = Instant::now();
+        let mut snapshot = Snapshot {
+            taken: Some(now),
+            ..Snapshot::default()
+        };
+        if needs(&[Stats::Kernel]) {
+            snapshot.os_name = sys.name();
+            snapshot.kernel_version = sys.kernel_version();
+            snapshot.os_version = sys.os_version();
+        }
+        if needs(&[Stats::Uptime]) {
+            sys.refresh_users_list();
+            snapshot.uptime = sys.uptime();
+            snapshot.users = sys.users().len();
+            let load = sys.load_average();
+            snapshot.load = (load.one, load.five, load.fifteen);
+        }
+        if needs(&[Stats::Memory, Stats::Swap]) {
+            sys.refresh_memory();
+            snapshot.memory = Memory {
+                total: sys.total_memory(),
+                used: sys.used_memory(),
+                available: sys.available_memory(),
+                swap_total: sys.total_swap(),
+                swap_used: sys.used_swap(),
+                swap_free: sys.free_swap(),
+            };
+        }
+        if needs(&[Stats::Disks]) {
+            sys.refresh_disks_list();
+            snapshot.disks = sys
+                .disks()
+                .iter()
+                .map(|d| Disk {
+                    mount: d.mount_point().to_string_lossy().into_owned(),
+                    file_system: String::from_utf8_lossy(d.file_system()).into_owned(),
+                    total: d.total_space(),
+                    available: d.available_space(),
+                })
+                .collect();
+        }
+        if needs(&[Stats::Network]) {
+            sys.refresh_networks_list();
+            let mut interfaces = vec![];
+            for (name, data) in sys.networks() {
+                let received = data.total_received();
+                let transmitted = data.total_transmitted();
+                let (rx_rate, tx_rate) = match self.traffic.get(name) {
+                    Some(&(rx, tx, then)) => (
+                        rate(received, rx, now - then),
+                        rate(transmitted, tx, now - then),
+                    ),
+                    None => (None, None),
+                };
+                self.traffic
+                    .insert(name.clone(), (received, transmitted, now));
+                interfaces.push(Interface {
+                    name: name.clone(),
+                    received,
+                    transmitted,
+                    rx_rate,
+                    tx_rate,
+                });
+            }
+            interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+            snapshot.interfaces = interfaces;
+        }
+        if needs(&[Stats::Cpu, Stats::Sensors]) {
+            if sys.components().is_empty() {
+                sys.refresh_components_list();
+            } else {
+                sys.refresh_components();
+            }
+            snapshot.sensors = sys
+                .components()
+                .iter()
+                .map(|c| Sensor {
+                    label: String::from(c.label()),
+                    temperature: c.temperature(),
+                    max: Some(c.max()).filter(|x| !x.is_nan()),
+                    critical: c.critical(),
+                })
+                .collect();
+        }
+        snapshot
+    }
+}
+
+/// Takes a single snapshot, for a process which only serves one query
+pub fn collect_once(cfg: &Config) {
+    let mut collector = Collector {
+        sys: System::new(),
+        traffic: HashMap::new(),
+    };
+    *SNAPSHOT.write().unwrap() = Arc::new(collector.collect(cfg));
+}
+
+/// Takes the first snapshot, then starts the thread which keeps it up to
+/// date. `config` is called before each scan, so that changes to the config
+/// are picked up on reload.
+pub fn start<F>(config: F)
+where
+    F: Fn() -> Arc<Config> + Send + 'static,
+{
+    let mut collector = Collector {
+        sys: System::new(),
+        traffic: HashMap::new(),
+    };
+    let cfg = config();
+    *SNAPSHOT.write().unwrap() = Arc::new(collector.collect(&cfg));
+    drop(cfg);
+    thread::spawn(move || loop {
+        let interval = config().stats_interval;
+        thread::sleep(Duration::from_secs(interval));
+        let snapshot = collector.collect(&config());
+        *SNAPSHOT.write().unwrap() = Arc::new(snapshot);
+    });
+}
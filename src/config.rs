@@ -1,15 +1,20 @@
 use {
-    serde::Deserialize,
+    crate::{access::Cidr, exec, log::error, template::Template, username::Username},
+    serde::{Deserialize, Serialize},
     std::{
-        env,
+        collections::BTreeMap,
         ffi::CString,
         fs,
         io::{Error, ErrorKind},
+        net::{IpAddr, SocketAddr},
+        num::NonZeroUsize,
+        path::{Path, PathBuf},
     },
 };
 
 #[allow(clippy::unsafe_derive_deserialize)]
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     /// The name for this server
     pub server: String,
@@ -17,6 +22,9 @@ pub struct Config {
     pub address: String,
     /// The port to run on
     pub port: String,
+    /// The socket addresses to listen on. If empty, `address` and `port` are
+    /// used instead.
+    pub listen: Vec<String>,
     /// The user the server should run as
     pub user: String,
     /// The group the server should run as
@@ -25,17 +33,489 @@ pub struct Config {
     pub root: String,
     /// Whether or not to chroot into the server root
     pub chroot: bool,
-    /// The number of worker threads used to server requests
-    pub threads: usize,
+    /// The number of worker threads used to serve requests
+    pub threads: NonZeroUsize,
+    /// The number of connections which may wait for a free worker thread
+    pub queue: NonZeroUsize,
+    /// What to do with new connections when the queue is full
+    pub overload: Overload,
     pub stats: Vec<Stats>,
+    /// How often the system stats are refreshed, in seconds
+    pub stats_interval: u64,
+    /// A template for the server info page, relative to the directory holding
+    /// the config file. If empty, the stats are shown in a fixed layout.
+    pub template: String,
+    /// The parsed contents of `template`
+    #[serde(skip)]
+    pub layout: Option<Template>,
+    /// How symbolic links in user directories are treated
+    pub symlinks: Symlinks,
+    /// The files in each user's directory which make up the response to a
+    /// query for that user, in order
+    pub files: Vec<UserFile>,
+    /// Time and size limits for each connection
+    pub connection: Connection,
+    /// Limits on how often and how many connections each client may make
+    pub rate_limit: RateLimit,
+    /// Which networks may make each kind of query
+    pub access: Access,
+    /// Where and how each query is logged
+    pub access_log: AccessLog,
+    /// Where server messages are logged, and how much detail is kept
+    pub log: Log,
+    /// Which details about a user are shown above their files
+    pub header: Header,
+    /// Settings for forwarding queries on to other hosts
+    pub forward: Forward,
+    /// Which columns are shown by the `Listing` stat
+    pub listing: Listing,
+    /// Which filesystems are shown by the `Disks` stat
+    pub disks: Disks,
+    /// Which interfaces are shown by the `Network` stat
+    pub network: Network,
+    /// Which temperatures are shown by the `Sensors` stat, and how
+    pub sensors: Sensors,
+    /// Limits on executable plans
+    pub exec: Exec,
+    /// Keeping users' files in memory between queries
+    pub cache: Cache,
+    /// How the contents of users' files are prepared before they are sent
+    pub content: Content,
+    /// Settings for individual users, keyed by username
+    pub users: BTreeMap<String, User>,
 }
 
-#[derive(Deserialize, PartialEq)]
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
 pub enum Stats {
+    /// The names of the users sharing files
     Users,
+    /// A table of the users logged in, with their names, terminals and
+    /// whether they share a plan
+    Listing,
     Uptime,
     Kernel,
     Cpu,
+    /// Total, used and available memory
+    Memory,
+    Swap,
+    /// Space on each mounted filesystem
+    Disks,
+    /// Traffic on each network interface
+    Network,
+    /// Every hardware temperature sensor, filtered and renamed as set in the
+    /// `[sensors]` section
+    Sensors,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Overload {
+    /// Reply with a short message telling the client to try again later
+    #[default]
+    Busy,
+    /// Close the connection without replying
+    Close,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Symlinks {
+    /// Follow symlinks wherever they lead
+    Follow,
+    /// Follow symlinks only if their target is inside of the server root
+    #[default]
+    WithinRoot,
+    /// Never follow symlinks
+    Deny,
+}
+
+/// How the text of users' files is converted before it is sent
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Charset {
+    /// Send the bytes unchanged, whatever their encoding
+    PassThrough,
+    /// Send UTF-8, replacing any invalid bytes
+    #[default]
+    Lossy,
+    /// Send plain ASCII, approximating accented letters and punctuation.
+    /// Files which are not UTF-8 are taken to be Latin-1.
+    Ascii,
+}
+
+/// A file in each user's directory which is included in responses
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserFile {
+    /// The file name, such as `.plan`
+    pub name: String,
+    /// Shown above the contents of the file, if not empty
+    pub heading: String,
+    /// Whether the file is only shown in response to `/W` queries
+    pub verbose_only: bool,
+}
+
+impl UserFile {
+    fn new(name: &str, heading: &str, verbose_only: bool) -> Self {
+        Self {
+            name: String::from(name),
+            heading: String::from(heading),
+            verbose_only,
+        }
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct User {
+    /// The names of the files which this user shares. If not set, all of the
+    /// files in the global list are shared.
+    pub files: Option<Vec<String>>,
+    /// Whether an executable `.plan`, or the programs in `.plan.d`, are run
+    /// to produce this user's plan
+    pub exec: bool,
+    /// Whether colours and text styles are kept in the response to a query
+    /// for this user, which covers their files and the header above them
+    pub colour: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Connection {
+    /// Seconds to wait for the client to send more of its query
+    pub read_timeout: u64,
+    /// Seconds to wait for the client to accept more of the response
+    pub write_timeout: u64,
+    /// Seconds after which the connection is closed, however far along it is
+    pub deadline: u64,
+    /// The maximum length of a query in bytes
+    pub max_query: usize,
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Self {
+            read_timeout: 5,
+            write_timeout: 10,
+            deadline: 30,
+            max_query: 512,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Aggregate {
+    /// Track each IPv4 client address separately
+    #[default]
+    Address,
+    /// Track IPv4 clients by their /24 network. IPv6 clients are always
+    /// tracked by their /64 network.
+    Prefix,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimit {
+    /// Whether or not to limit the rate of new connections
+    pub enabled: bool,
+    /// The number of connections per second which a client may make over time
+    pub rate: f64,
+    /// The number of connections which a client may make in a quick burst
+    pub burst: u32,
+    /// How clients are grouped together when counting connections
+    pub aggregate: Aggregate,
+    /// The maximum number of connections which a client may have open at once,
+    /// or 0 for no limit
+    pub max_concurrent: usize,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rate: 1.0,
+            burst: 10,
+            aggregate: Aggregate::default(),
+            max_concurrent: 0,
+        }
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Access {
+    /// Rules for the empty query, which returns the server info
+    pub info: Rules,
+    /// Rules for queries about a user on this host
+    pub user: Rules,
+    /// Rules for queries to be forwarded to another host
+    pub forward: Rules,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rules {
+    /// If not empty, only these networks are allowed
+    pub allow: Vec<Cidr>,
+    /// These networks are always refused
+    pub deny: Vec<Cidr>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum LogFormat {
+    /// A single line similar to the common log format used by web servers
+    #[default]
+    Common,
+    /// A JSON object on a single line
+    Json,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Destination {
+    #[default]
+    Stdout,
+    /// The file given by `path`
+    File,
+    /// The local syslog daemon
+    Syslog,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessLog {
+    /// Whether or not to log each query
+    pub enabled: bool,
+    pub format: LogFormat,
+    pub destination: Destination,
+    /// The path to the log file when `destination` is `File`
+    pub path: String,
+}
+
+/// The severity of a log message, from most to least severe
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Stderr,
+    /// The local syslog daemon
+    Syslog,
+    /// The systemd journal, using its native protocol
+    Journald,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Log {
+    pub backend: Backend,
+    /// Messages less severe than this are discarded
+    pub level: Level,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Listing {
+    /// Whether the terminal of each session is shown
+    pub tty: bool,
+    /// Whether the time since each terminal was last used is shown
+    pub idle: bool,
+}
+
+impl Default for Listing {
+    fn default() -> Self {
+        Self {
+            tty: true,
+            idle: true,
+        }
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Disks {
+    /// The mount points to show. If empty, every filesystem is shown.
+    pub mounts: Vec<String>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Network {
+    /// The interfaces to show. If empty, every interface but loopback is
+    /// shown.
+    pub interfaces: Vec<String>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Units {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+/// Renames sensors whose labels start with `from`, replacing that part of the
+/// label with `to`
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sensors {
+    /// Patterns for the sensor labels to show, where `*` matches anything and
+    /// `?` matches a single character. If empty, every sensor is shown.
+    pub include: Vec<String>,
+    /// Patterns for sensor labels to leave out, even if they are included
+    pub exclude: Vec<String>,
+    /// The units for this and the `Cpu` stat
+    pub units: Units,
+    /// Applied in order, with only the first matching rule used
+    pub rename: Vec<Rename>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Exec {
+    /// Seconds allowed for all of a user's plan programs to finish
+    pub timeout: u64,
+    /// The most output, in bytes, which is kept from a user's plan programs
+    pub max_output: usize,
+    /// Seconds of cpu time each program may use
+    pub cpu_time: u64,
+    /// Address space each program may use, in MiB
+    pub memory: u64,
+    /// The most processes which the owner of a plan may have while it runs,
+    /// counting every process they own
+    pub processes: u64,
+}
+
+impl Default for Exec {
+    fn default() -> Self {
+        Self {
+            timeout: 5,
+            max_output: 65536,
+            cpu_time: 2,
+            memory: 256,
+            processes: 32,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cache {
+    pub enabled: bool,
+    /// The most memory, in bytes, which the cached files may take up
+    pub max_size: usize,
+    /// Seconds for which a cached file is served without checking whether it
+    /// has changed
+    pub ttl: u64,
+    /// How often, in seconds, the cache's hit and miss counts are logged, or
+    /// 0 to only log them on reload and shutdown
+    pub log_interval: u64,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_size: 1024 * 1024,
+            ttl: 1,
+            log_interval: 3600,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Content {
+    /// The most of each file, in bytes, which is sent
+    pub max_size: usize,
+    pub charset: Charset,
+}
+
+impl Default for Content {
+    fn default() -> Self {
+        Self {
+            max_size: 65536,
+            charset: Charset::default(),
+        }
+    }
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Header {
+    /// Whether the header is only shown in response to `/W` queries
+    pub verbose_only: bool,
+    /// The passwd file, relative to the server root
+    pub passwd: String,
+    /// A copy of the utmp file, relative to the server root, which lists the
+    /// users currently logged in. Not read if empty.
+    pub utmp: String,
+    /// A copy of the wtmp file, relative to the server root. Not read if empty.
+    pub wtmp: String,
+    /// A copy of the lastlog file, relative to the server root. Not read if
+    /// empty.
+    pub lastlog: String,
+    pub login: bool,
+    /// The user's full name, from the GECOS field
+    pub name: bool,
+    pub directory: bool,
+    pub shell: bool,
+    /// When the user last logged in, or whether they are logged in now
+    pub last_login: bool,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self {
+            verbose_only: true,
+            passwd: String::from("etc/passwd"),
+            utmp: String::new(),
+            wtmp: String::new(),
+            lastlog: String::new(),
+            login: true,
+            name: true,
+            directory: false,
+            shell: false,
+            last_login: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Forward {
+    /// Whether or not to forward `user@host` queries on to other hosts
+    pub enabled: bool,
+    /// The hosts which queries may be forwarded to
+    pub allow: Vec<String>,
+    /// The maximum number of hosts which a query may name
+    pub max_hops: usize,
+    /// Seconds to wait when connecting to another host
+    pub connect_timeout: u64,
+    /// Seconds to wait for another host to respond
+    pub read_timeout: u64,
+}
+
+impl Default for Forward {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            allow: vec![],
+            max_hops: 1,
+            connect_timeout: 5,
+            read_timeout: 10,
+        }
+    }
 }
 
 impl Default for Config {
@@ -44,46 +524,292 @@ impl Default for Config {
             server: String::from("localhost"),
             address: String::from("0.0.0.0"),
             port: String::from("79"),
+            listen: vec![],
             user: String::from("toe"),
             group: String::from("toe"),
             root: String::from("/srv"),
-            threads: 4,
+            threads: NonZeroUsize::new(4).unwrap(),
+            queue: NonZeroUsize::new(64).unwrap(),
+            overload: Overload::default(),
             chroot: true,
             stats: vec![],
+            stats_interval: 5,
+            template: String::new(),
+            layout: None,
+            symlinks: Symlinks::default(),
+            files: vec![
+                UserFile::new(".project", "Project:", true),
+                UserFile::new(".plan", "Plan:", false),
+                UserFile::new(".pgpkey", "PGP key:", true),
+                UserFile::new(".pubkey", "Public key:", true),
+            ],
+            connection: Connection::default(),
+            rate_limit: RateLimit::default(),
+            access: Access::default(),
+            access_log: AccessLog::default(),
+            log: Log::default(),
+            header: Header::default(),
+            forward: Forward::default(),
+            listing: Listing::default(),
+            disks: Disks::default(),
+            network: Network::default(),
+            sensors: Sensors::default(),
+            exec: Exec::default(),
+            cache: Cache::default(),
+            content: Content::default(),
+            users: BTreeMap::new(),
         }
     }
 }
 
 impl Config {
-    pub fn load() -> Result<Self, Error> {
-        let args: Vec<String> = env::args().collect();
-        for arg in &args {
-            println!("Arg: {arg}");
+    /// Reads the config file at `path`. Any keys which are missing from the file
+    /// take their default values.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let raw = fs::read_to_string(path)
+            .map_err(|e| Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+        Self::from_toml(&raw, path)
+    }
+
+    /// Decodes the contents of a config file which was read from `path`
+    pub fn from_toml(raw: &str, path: &Path) -> Result<Self, Error> {
+        toml::from_str(raw).map_err(|e| Error::other(format!("{}: {e}", path.display())))
+    }
+
+    /// Reads and parses the template, if one is set, along with the files it
+    /// includes. `read` is given the path to each file, which is relative to
+    /// the directory holding the config file.
+    pub fn load_template(
+        &mut self,
+        read: impl Fn(&Path) -> Result<String, Error>,
+    ) -> Result<(), Error> {
+        if self.template.is_empty() {
+            return Ok(());
+        }
+        let raw = read(Path::new(&self.template))
+            .map_err(|e| Error::new(e.kind(), format!("template {}: {e}", self.template)))?;
+        let mut layout = raw
+            .parse::<Template>()
+            .map_err(|e| Error::other(format!("template {}: {e}", self.template)))?;
+        layout
+            .read_includes(read)
+            .map_err(|e| Error::new(e.kind(), format!("template {}: {e}", self.template)))?;
+        self.layout = Some(layout);
+        Ok(())
+    }
+
+    /// Checks the values which cannot be verified while decoding the file
+    pub fn validate(&self) -> Result<(), Error> {
+        self.listen_addrs()?;
+        for file in &self.files {
+            if file.name.is_empty()
+                || file.name == "."
+                || file.name == ".."
+                || file.name.contains('/')
+            {
+                return Err(Error::other(format!(
+                    "files: `{}` is not a valid file name",
+                    file.name
+                )));
+            }
+        }
+        for (name, user) in &self.users {
+            if let Err(e) = name.parse::<Username>() {
+                return Err(Error::other(format!("users.{name}: {e}")));
+            }
+            for file in user.files.iter().flatten() {
+                if !self.files.iter().any(|f| &f.name == file) {
+                    return Err(Error::other(format!(
+                        "users.{name}: `{file}` is not in the list of files"
+                    )));
+                }
+            }
+        }
+        if self.stats_interval == 0 {
+            return Err(Error::other("stats_interval must be greater than 0"));
+        }
+        if self.connection.read_timeout == 0
+            || self.connection.write_timeout == 0
+            || self.connection.deadline == 0
+        {
+            return Err(Error::other(
+                "connection timeouts and deadline must be greater than 0",
+            ));
+        }
+        if self.rate_limit.enabled && !(self.rate_limit.rate > 0.0 && self.rate_limit.burst > 0) {
+            return Err(Error::other(
+                "rate_limit.rate and rate_limit.burst must be greater than 0",
+            ));
+        }
+        if self.access_log.enabled
+            && self.access_log.destination == Destination::File
+            && self.access_log.path.is_empty()
+        {
+            return Err(Error::other(
+                "access_log.path must be set when logging to a file",
+            ));
+        }
+        if self.exec.timeout == 0 || self.exec.max_output == 0 || self.exec.processes == 0 {
+            return Err(Error::other(
+                "exec.timeout, exec.max_output and exec.processes must be greater than 0",
+            ));
+        }
+        if self.content.max_size == 0 {
+            return Err(Error::other("content.max_size must be greater than 0"));
+        }
+        if self.forward.enabled && self.forward.max_hops == 0 {
+            return Err(Error::other(
+                "forward.max_hops must be greater than 0 when forwarding is enabled",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The addresses to listen on, from either `listen` or `address` and `port`
+    pub fn listen_addrs(&self) -> Result<Vec<SocketAddr>, Error> {
+        if self.listen.is_empty() {
+            let ip = self
+                .address
+                .parse::<IpAddr>()
+                .map_err(|e| Error::other(format!("invalid address `{}`: {e}", self.address)))?;
+            let port = self
+                .port
+                .parse::<u16>()
+                .map_err(|e| Error::other(format!("invalid port `{}`: {e}", self.port)))?;
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        self.listen
+            .iter()
+            .map(|x| {
+                x.parse::<SocketAddr>()
+                    .map_err(|e| Error::other(format!("invalid listen address `{x}`: {e}")))
+            })
+            .collect()
+    }
+
+    /// Checks that the server root exists. This can only be done before
+    /// entering the chroot.
+    pub fn check_root(&self) -> Result<(), Error> {
+        match fs::metadata(&self.root) {
+            Ok(meta) if meta.is_dir() => {}
+            Ok(_) => {
+                return Err(Error::other(format!(
+                    "root `{}` is not a directory",
+                    self.root
+                )))
+            }
+            Err(e) => return Err(Error::new(e.kind(), format!("root `{}`: {e}", self.root))),
+        }
+        Ok(())
+    }
+
+    /// Copies the settings which can only take effect at startup from the
+    /// running config, returning the names of any which differ
+    pub fn keep_restart_settings(&mut self, running: &Self) -> Vec<&'static str> {
+        let mut changed = vec![];
+        macro_rules! keep {
+            ($($($field:ident).+),*) => {
+                $(
+                    if self.$($field).+ != running.$($field).+ {
+                        changed.push(stringify!($($field).+));
+                        self.$($field).+.clone_from(&running.$($field).+);
+                    }
+                )*
+            };
+        }
+        keep!(
+            address,
+            port,
+            listen,
+            user,
+            group,
+            root,
+            chroot,
+            threads,
+            queue,
+            access_log,
+            log.backend
+        );
+        // Plans are run by a helper which is only started if someone could
+        // run them when toe started
+        if !exec::started() && self.users.values().any(|u| u.exec) {
+            changed.push("users.exec");
+            for user in self.users.values_mut() {
+                user.exec = false;
+            }
         }
-        let raw = fs::read_to_string("/etc/toe.toml")?;
-        match toml::from_str(&raw) {
-            Ok(c) => Ok(c),
-            Err(_) => Err(Error::new(ErrorKind::Other, "Error decoding config file")),
+        changed
+    }
+
+    /// The files which make up the response to a query for `name`
+    pub fn user_files<'a>(
+        &'a self,
+        name: &'a str,
+        verbose: bool,
+    ) -> impl Iterator<Item = &'a UserFile> + 'a {
+        let shared = self.users.get(name).and_then(|u| u.files.as_ref());
+        self.files.iter().filter(move |f| {
+            (verbose || !f.verbose_only) && shared.is_none_or(|s| s.contains(&f.name))
+        })
+    }
+
+    /// Whether `name` is allowed to run executable plans
+    pub fn can_exec(&self, name: &str) -> bool {
+        self.users.get(name).is_some_and(|u| u.exec)
+    }
+
+    /// Whether `name` is allowed to colour their files
+    pub fn allows_colour(&self, name: &str) -> bool {
+        self.users.get(name).is_some_and(|u| u.colour)
+    }
+
+    /// The server root as seen by the running process, which is "/" after
+    /// entering the chroot
+    pub fn server_root(&self) -> PathBuf {
+        if self.chroot {
+            PathBuf::from("/")
+        } else {
+            PathBuf::from(&self.root)
         }
     }
 
+    /// The path to a file given relative to the server root, as seen by the
+    /// running process
+    pub fn root_path(&self, path: &str) -> PathBuf {
+        self.server_root().join(path.trim_start_matches('/'))
+    }
+
     pub fn getpwnam(&self) -> Result<*mut libc::passwd, Error> {
         let user = CString::new(self.user.as_bytes())?;
+        unsafe { *libc::__errno_location() = 0 };
         let uid = unsafe { libc::getpwnam(user.as_ptr()) };
         if uid.is_null() {
-            eprintln!("Unable to getpwnam of user: {}", &self.user);
-            return Err(Error::last_os_error());
+            return Err(lookup_error("user", &self.user));
         }
         Ok(uid)
     }
 
     pub fn getgrnam(&self) -> Result<*mut libc::group, Error> {
         let group = CString::new(self.group.as_bytes())?;
+        unsafe { *libc::__errno_location() = 0 };
         let gid = unsafe { libc::getgrnam(group.as_ptr()) };
         if gid.is_null() {
-            eprintln!("Unable to get getgrnam of group: {}", &self.group);
-            return Err(Error::last_os_error());
+            return Err(lookup_error("group", &self.group));
         }
         Ok(gid)
     }
 }
+
+/// The error after `getpwnam` or `getgrnam` fails to find `name`. A name which
+/// does not exist leaves errno unset, or set to one of a few codes.
+fn lookup_error(kind: &str, name: &str) -> Error {
+    let e = Error::last_os_error();
+    if let Some(0 | libc::ENOENT | libc::ESRCH | libc::EBADF | libc::EPERM) = e.raw_os_error() {
+        return Error::new(
+            ErrorKind::NotFound,
+            format!("{kind} `{name}` does not exist"),
+        );
+    }
+    error!("Unable to look up {kind}: {name}");
+    e
+}
@@ -0,0 +1,96 @@
+//! Reading queries from and writing responses to clients within the configured
+//! time limits
+use {
+    crate::config::Connection,
+    std::{
+        cmp,
+        io::{Error, ErrorKind, Read, Write},
+        net::TcpStream,
+        time::{Duration, Instant},
+    },
+};
+
+/// Returns whichever is sooner of `timeout` or the time left until `deadline`
+pub fn remaining(timeout: u64, deadline: Instant) -> Result<Duration, Error> {
+    let left = deadline.saturating_duration_since(Instant::now());
+    if left.is_zero() {
+        return Err(Error::new(
+            ErrorKind::TimedOut,
+            "connection deadline passed",
+        ));
+    }
+    Ok(cmp::min(Duration::from_secs(timeout), left))
+}
+
+/// Reads from the client until the end of the first line, returning the line
+/// without its terminator. Fails with `ErrorKind::InvalidData` if the line is
+/// longer than `max_query` bytes.
+pub fn read_query(
+    stream: &mut TcpStream,
+    limits: &Connection,
+    deadline: Instant,
+) -> Result<Vec<u8>, Error> {
+    let mut query = Vec::with_capacity(64);
+    let mut buf = [0; 512];
+    loop {
+        stream.set_read_timeout(Some(remaining(limits.read_timeout, deadline)?))?;
+        let len = match stream.read(&mut buf) {
+            Ok(len) => len,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "timed out waiting for query",
+                ));
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let start = query.len();
+        query.extend_from_slice(&buf[..len]);
+        if let Some(end) = query[start..].iter().position(|b| *b == b'\n') {
+            query.truncate(start + end);
+            if query.last() == Some(&b'\r') {
+                query.pop();
+            }
+            break;
+        }
+        if query.len() > limits.max_query {
+            return Err(Error::new(ErrorKind::InvalidData, "query too long"));
+        }
+        if len == 0 {
+            // The client closed its end without sending a line terminator
+            break;
+        }
+    }
+    if query.len() > limits.max_query {
+        return Err(Error::new(ErrorKind::InvalidData, "query too long"));
+    }
+    Ok(query)
+}
+
+/// Writes the full response to the client, giving up if the client does not
+/// accept it before the deadline
+pub fn write_response(
+    stream: &mut TcpStream,
+    limits: &Connection,
+    deadline: Instant,
+    response: &[u8],
+) -> Result<(), Error> {
+    let mut written = 0;
+    while written < response.len() {
+        stream.set_write_timeout(Some(remaining(limits.write_timeout, deadline)?))?;
+        match stream.write(&response[written..]) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(len) => written += len,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "timed out writing response",
+                ));
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,194 @@
+//! Preparing the contents of users' files to be sent. Files are cut short at
+//! the configured size and converted to the configured charset. Each file is
+//! then run through the filter on its own, so that an escape sequence left
+//! unfinished at the end of one cannot swallow whatever follows it in the
+//! response, which passes through the filter again as a whole.
+use {
+    crate::{
+        config::{Charset, Content},
+        filter,
+    },
+    std::str,
+};
+
+/// Prepares `raw` to be sent. `raw` may hold more than `max_size` bytes, in
+/// which case the rest is left out and a notice is added. Colours and text
+/// styles are kept if `colour` is set.
+pub fn prepare(cfg: &Content, raw: &[u8], colour: bool) -> Vec<u8> {
+    let truncated = raw.len() > cfg.max_size;
+    let mut raw = &raw[..raw.len().min(cfg.max_size)];
+    if truncated {
+        // Don't leave half of a character at the end
+        if let Err(e) = str::from_utf8(raw) {
+            if e.error_len().is_none() {
+                raw = &raw[..e.valid_up_to()];
+            }
+        }
+    }
+    let converted = match cfg.charset {
+        Charset::PassThrough => raw.to_vec(),
+        Charset::Lossy => String::from_utf8_lossy(raw).into_owned().into_bytes(),
+        Charset::Ascii => ascii(raw).into_bytes(),
+    };
+    let mut out = filter::apply(&converted, colour);
+    if truncated {
+        if !out.ends_with(b"\n") {
+            out.push(b'\n');
+        }
+        out.extend(format!("[Truncated to {} bytes]\n", cfg.max_size).into_bytes());
+    }
+    out
+}
+
+/// Converts UTF-8, or Latin-1 if `raw` is not valid UTF-8, to plain ASCII
+fn ascii(raw: &[u8]) -> String {
+    let chars: Box<dyn Iterator<Item = char>> = match str::from_utf8(raw) {
+        Ok(s) => Box::new(s.chars()),
+        Err(_) => Box::new(raw.iter().map(|&b| char::from(b))),
+    };
+    chars.fold(String::new(), |mut s, c| {
+        if c.is_ascii() {
+            s.push(c);
+        } else {
+            s.push_str(transliterate(c));
+        }
+        s
+    })
+}
+
+/// The closest ASCII to a character, or `?` if there is nothing close
+fn transliterate(c: char) -> &'static str {
+    match c {
+        'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Ç' | 'Ć' | 'Č' => "C",
+        'ç' | 'ć' | 'č' => "c",
+        'Ð' | 'Ď' | 'Đ' => "D",
+        'ð' | 'ď' | 'đ' => "d",
+        'È' | 'É' | 'Ê' | 'Ë' | 'Ē' | 'Ė' | 'Ę' | 'Ě' => "E",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+        'Ğ' => "G",
+        'ğ' => "g",
+        'Ì' | 'Í' | 'Î' | 'Ï' | 'Ī' | 'İ' => "I",
+        'ì' | 'í' | 'î' | 'ï' | 'ī' | 'ı' => "i",
+        'Ł' => "L",
+        'ł' => "l",
+        'Ñ' | 'Ń' | 'Ň' => "N",
+        'ñ' | 'ń' | 'ň' => "n",
+        'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' | 'Ō' | 'Ő' => "O",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+        'Œ' => "OE",
+        'œ' => "oe",
+        'Ř' => "R",
+        'ř' => "r",
+        'Ś' | 'Š' | 'Ş' => "S",
+        'ś' | 'š' | 'ş' => "s",
+        'ß' => "ss",
+        'Ť' | 'Ţ' => "T",
+        'ť' | 'ţ' => "t",
+        'Þ' => "TH",
+        'þ' => "th",
+        'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ū' | 'Ů' | 'Ű' => "U",
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' => "u",
+        'Ý' | 'Ÿ' => "Y",
+        'ý' | 'ÿ' => "y",
+        'Ź' | 'Ż' | 'Ž' => "Z",
+        'ź' | 'ż' | 'ž' => "z",
+        '\u{a0}' | '\u{2002}'..='\u{200a}' => " ",
+        '‘' | '’' | '‚' | '′' => "'",
+        '“' | '”' | '„' | '″' => "\"",
+        '‐' | '‑' | '‒' | '–' | '−' => "-",
+        '—' | '―' => "--",
+        '…' => "...",
+        '•' | '·' => "*",
+        '«' => "<<",
+        '»' => ">>",
+        '©' => "(c)",
+        '®' => "(R)",
+        '™' => "(TM)",
+        '×' => "x",
+        '÷' => "/",
+        '€' => "EUR",
+        _ => "?",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content(max_size: usize, charset: Charset) -> Content {
+        Content { max_size, charset }
+    }
+
+    #[test]
+    fn untouched() {
+        let cfg = content(64, Charset::PassThrough);
+        assert_eq!(prepare(&cfg, b"plan\n", false), b"plan\n");
+    }
+
+    #[test]
+    fn truncated() {
+        let cfg = content(4, Charset::PassThrough);
+        assert_eq!(
+            prepare(&cfg, b"abcdef", false),
+            b"abcd\n[Truncated to 4 bytes]\n"
+        );
+        assert_eq!(
+            prepare(&cfg, b"abc\ndef", false),
+            b"abc\n[Truncated to 4 bytes]\n"
+        );
+        // Exactly `max_size` bytes are not cut
+        assert_eq!(prepare(&cfg, b"abcd", false), b"abcd");
+    }
+
+    #[test]
+    fn truncated_mid_character() {
+        let cfg = content(4, Charset::PassThrough);
+        assert_eq!(
+            prepare(&cfg, "abcé".as_bytes(), false),
+            b"abc\n[Truncated to 4 bytes]\n"
+        );
+    }
+
+    #[test]
+    fn unfinished_sequence() {
+        // The title sequence ends with the file, rather than swallowing the
+        // notice
+        let cfg = content(10, Charset::PassThrough);
+        assert_eq!(
+            prepare(&cfg, b"plan\n\x1b]0;title and more", false),
+            b"plan\n[Truncated to 10 bytes]\n"
+        );
+        let cfg = content(64, Charset::PassThrough);
+        assert_eq!(prepare(&cfg, b"\x1b[1mbold", true), b"\x1b[1mbold\x1b[0m");
+    }
+
+    #[test]
+    fn lossy() {
+        let cfg = content(64, Charset::Lossy);
+        assert_eq!(prepare(&cfg, b"a\xffb", false), "a\u{fffd}b".as_bytes());
+    }
+
+    #[test]
+    fn ascii() {
+        let cfg = content(64, Charset::Ascii);
+        assert_eq!(
+            prepare(&cfg, "Zoë’s café — “naïve” ☃".as_bytes(), false),
+            b"Zoe's cafe -- \"naive\" ?"
+        );
+        // Not UTF-8, so read as Latin-1
+        assert_eq!(prepare(&cfg, b"caf\xe9", false), b"cafe");
+    }
+
+    #[test]
+    fn transliterated() {
+        assert_eq!(transliterate('Æ'), "AE");
+        assert_eq!(transliterate('ß'), "ss");
+        assert_eq!(transliterate('…'), "...");
+        assert_eq!(transliterate('€'), "EUR");
+        assert_eq!(transliterate('日'), "?");
+    }
+}
@@ -0,0 +1,656 @@
+//! Running executable plans. A user who has been allowed to in the config may
+//! make their `.plan` executable, or create a `.plan.d` directory of
+//! executables, and the output is served in place of the file's contents.
+//! Each program runs as the owner of the user's directory, with no stdin, an
+//! empty environment apart from a few variables, resource limits and a
+//! timeout, and its output is capped.
+//!
+//! Programs are started and killed by a helper process, which is forked before
+//! toe drops its privileges. Only the helper keeps the capabilities needed to
+//! switch to the owners and to kill their programs, so the threads which serve
+//! the network have none. Workers pass it each program's open file, and it
+//! passes back the program's stdout.
+use {
+    crate::{
+        config::{Config, Exec},
+        log::warning,
+        username::Username,
+    },
+    std::{
+        collections::HashMap,
+        ffi::OsStr,
+        fs::{self, File},
+        io::{Error, ErrorKind, Read},
+        mem,
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+            unix::{
+                ffi::OsStrExt,
+                fs::{MetadataExt, PermissionsExt},
+                process::{CommandExt, ExitStatusExt},
+            },
+        },
+        path::{Path, PathBuf},
+        process::{self, Child, Command, ExitStatus, Stdio},
+        sync::{Mutex, OnceLock},
+        time::Instant,
+    },
+};
+
+/// The search path given to plan programs
+const PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// Capability numbers from `linux/capability.h`
+const CAP_KILL: u32 = 5;
+const CAP_SETGID: u32 = 6;
+const CAP_SETUID: u32 = 7;
+const CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// How many times `kill_all` looks for processes to kill, in case any were
+/// started while it was killing the others
+const KILL_ROUNDS: usize = 8;
+
+/// The largest message passed between the workers and the helper
+const MAX_MESSAGE: usize = 8192;
+
+/// The size of a file descriptor passed in a control message
+const FD_SIZE: u32 = 4;
+
+/// The socket to the helper, if it was started. Each request and its reply
+/// are exchanged while holding the lock, so that replies are never mixed up.
+static HELPER: OnceLock<Mutex<OwnedFd>> = OnceLock::new();
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Called before the helper drops privileges, so that the capabilities needed
+/// to start programs as their owners, and to kill them, survive the change of
+/// user
+fn keep_capabilities() -> Result<(), Error> {
+    if unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Called after the helper drops privileges, giving up every capability but
+/// those kept for running programs
+fn limit_capabilities() -> Result<(), Error> {
+    let caps = 1 << CAP_KILL | 1 << CAP_SETGID | 1 << CAP_SETUID;
+    let mut header = CapHeader {
+        version: CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data = [
+        CapData {
+            effective: caps,
+            permitted: caps,
+            inheritable: 0,
+        },
+        CapData::default(),
+    ];
+    if unsafe { libc::syscall(libc::SYS_capset, &raw mut header, data.as_ptr()) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    if unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 0, 0, 0, 0) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A request from a worker to the helper
+enum Request {
+    /// Start a program, whose open file is passed along with the request
+    Spawn(Spawn),
+    /// Kill a program and everything it started, then reap it
+    Finish(libc::pid_t),
+}
+
+struct Spawn {
+    cpu_time: u64,
+    /// In bytes
+    memory: u64,
+    processes: u64,
+    dir: PathBuf,
+    user: String,
+}
+
+impl Request {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Self::Spawn(spawn) => {
+                let mut data = vec![b'S'];
+                for limit in [spawn.cpu_time, spawn.memory, spawn.processes] {
+                    data.extend(limit.to_le_bytes());
+                }
+                data.extend(spawn.dir.as_os_str().as_bytes());
+                data.push(0);
+                data.extend(spawn.user.as_bytes());
+                data
+            }
+            Self::Finish(pid) => [&b"F"[..], &pid.to_le_bytes()].concat(),
+        }
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        match data.split_first()? {
+            (b'S', rest) => {
+                let (limits, rest) = rest.split_at_checked(24)?;
+                let mut limits = limits
+                    .chunks_exact(8)
+                    .filter_map(|c| c.try_into().ok())
+                    .map(u64::from_le_bytes);
+                let (dir, user) = rest.split_at(rest.iter().position(|&b| b == 0)?);
+                Some(Self::Spawn(Spawn {
+                    cpu_time: limits.next()?,
+                    memory: limits.next()?,
+                    processes: limits.next()?,
+                    dir: PathBuf::from(OsStr::from_bytes(dir)),
+                    user: String::from_utf8(user[1..].to_vec()).ok()?,
+                }))
+            }
+            (b'F', pid) => Some(Self::Finish(libc::pid_t::from_le_bytes(
+                pid.try_into().ok()?,
+            ))),
+            _ => None,
+        }
+    }
+}
+
+/// The reply to a request: a pid or exit status on success, or else an error
+/// message
+fn reply(result: &Result<i32, Error>) -> Vec<u8> {
+    match result {
+        Ok(n) => [&b"+"[..], &n.to_le_bytes()].concat(),
+        Err(e) => format!("-{e}").into_bytes(),
+    }
+}
+
+/// Sends a message over `socket`, passing the file descriptor `fd` with it if
+/// one is given
+fn send(socket: &OwnedFd, data: &[u8], fd: Option<RawFd>) -> Result<(), Error> {
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr().cast_mut().cast(),
+        iov_len: data.len(),
+    };
+    let mut control = [0u64; 4];
+    let mut msg = unsafe { mem::zeroed::<libc::msghdr>() };
+    msg.msg_iov = &raw mut iov;
+    msg.msg_iovlen = 1;
+    if let Some(fd) = fd {
+        msg.msg_control = control.as_mut_ptr().cast();
+        unsafe {
+            msg.msg_controllen = libc::CMSG_SPACE(FD_SIZE) as usize;
+            let cmsg = libc::CMSG_FIRSTHDR(&raw const msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(FD_SIZE) as usize;
+            libc::CMSG_DATA(cmsg).cast::<RawFd>().write_unaligned(fd);
+        }
+    }
+    loop {
+        if unsafe { libc::sendmsg(socket.as_raw_fd(), &raw const msg, libc::MSG_NOSIGNAL) } >= 0 {
+            return Ok(());
+        }
+        let e = Error::last_os_error();
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Receives a message from `socket`, along with the file descriptor passed with
+/// it if any. The message is empty once the other end has been closed.
+fn recv(socket: &OwnedFd) -> Result<(Vec<u8>, Option<OwnedFd>), Error> {
+    let mut data = vec![0; MAX_MESSAGE];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr().cast(),
+        iov_len: data.len(),
+    };
+    let mut control = [0u64; 4];
+    let mut msg = unsafe { mem::zeroed::<libc::msghdr>() };
+    msg.msg_iov = &raw mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&control);
+    let len = loop {
+        let len =
+            unsafe { libc::recvmsg(socket.as_raw_fd(), &raw mut msg, libc::MSG_CMSG_CLOEXEC) };
+        if let Ok(len) = usize::try_from(len) {
+            break len;
+        }
+        let e = Error::last_os_error();
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(e);
+        }
+    };
+    data.truncate(len);
+    let mut fd = None;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&raw const msg);
+        if !cmsg.is_null()
+            && (*cmsg).cmsg_level == libc::SOL_SOCKET
+            && (*cmsg).cmsg_type == libc::SCM_RIGHTS
+        {
+            let raw = libc::CMSG_DATA(cmsg).cast::<RawFd>().read_unaligned();
+            fd = Some(OwnedFd::from_raw_fd(raw));
+        }
+    }
+    Ok((data, fd))
+}
+
+/// Sends a request to the helper and waits for the reply, returning the pid or
+/// exit status in it and any file descriptor passed back
+fn transact(request: &Request, fd: Option<RawFd>) -> Result<(i32, Option<OwnedFd>), Error> {
+    let Some(helper) = HELPER.get() else {
+        return Err(Error::other(
+            "plans cannot be run, as no user could run them when toe started",
+        ));
+    };
+    let socket = helper.lock().unwrap();
+    if let Err(e) = send(&socket, &request.encode(), fd) {
+        return Err(if e.kind() == ErrorKind::BrokenPipe {
+            Error::other("the plan helper has exited")
+        } else {
+            e
+        });
+    }
+    let (reply, fd) = recv(&socket)?;
+    match reply.split_first() {
+        Some((b'+', n)) => match n.try_into() {
+            Ok(n) => Ok((i32::from_le_bytes(n), fd)),
+            Err(_) => Err(Error::other("invalid reply from the plan helper")),
+        },
+        Some((b'-', e)) => Err(Error::other(String::from_utf8_lossy(e).into_owned())),
+        _ => Err(Error::other("the plan helper has exited")),
+    }
+}
+
+/// Starts the helper which runs plan programs. This must be called while toe
+/// has a single thread, after entering the chroot and before dropping
+/// privileges. Given the server's user and group, the helper switches to them
+/// as well, but keeps the capabilities to start programs as their owners and
+/// to kill them. Otherwise programs run as the user toe runs as. Fails if
+/// `/proc` is not mounted.
+pub fn start(ids: Option<(libc::uid_t, libc::gid_t)>) -> Result<(), Error> {
+    // Without /proc, processes which leave a program's session could not be
+    // found, and those left to the helper would never be reaped
+    if !Path::new("/proc/self/stat").exists() {
+        return Err(Error::other(
+            "/proc must be mounted inside of the server root for plans to be run",
+        ));
+    }
+    let mut fds = [0; 2];
+    let kind = libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC;
+    if unsafe { libc::socketpair(libc::AF_UNIX, kind, 0, fds.as_mut_ptr()) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    let (server, helper) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    let parent = unsafe { libc::getpid() };
+    match unsafe { libc::fork() } {
+        -1 => return Err(Error::last_os_error()),
+        0 => {
+            drop(server);
+            let ready = setup(parent, ids).map(|()| 0);
+            if send(&helper, &reply(&ready), None).is_ok() && ready.is_ok() {
+                serve(&helper, ids.is_some());
+            }
+            process::exit(0);
+        }
+        _ => drop(helper),
+    }
+    match recv(&server)?.0.split_first() {
+        Some((b'+', _)) => {}
+        Some((b'-', e)) => {
+            let e = String::from_utf8_lossy(e);
+            return Err(Error::other(format!(
+                "unable to start the plan helper: {e}"
+            )));
+        }
+        _ => return Err(Error::other("the plan helper exited while starting")),
+    }
+    _ = HELPER.set(Mutex::new(server));
+    Ok(())
+}
+
+/// Whether the helper was started, without which no plans can be run
+pub fn started() -> bool {
+    HELPER.get().is_some()
+}
+
+/// Prepares the helper, in the child of the fork
+fn setup(parent: libc::pid_t, ids: Option<(libc::uid_t, libc::gid_t)>) -> Result<(), Error> {
+    unsafe {
+        // The helper goes when toe does
+        if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0) != 0 {
+            return Err(Error::last_os_error());
+        }
+        if libc::getppid() != parent {
+            return Err(Error::other("toe has exited"));
+        }
+        // Signals meant for toe are left to it, as its workers may still need
+        // the helper while it shuts down
+        libc::signal(libc::SIGINT, libc::SIG_IGN);
+        libc::signal(libc::SIGTERM, libc::SIG_IGN);
+        // Processes whose parent has exited are handed to the helper, so any
+        // child of the helper which is not a running program was left behind
+        // by one
+        if libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) != 0 {
+            return Err(Error::last_os_error());
+        }
+    }
+    if let Some((uid, gid)) = ids {
+        keep_capabilities()?;
+        if unsafe { libc::setgroups(0, std::ptr::null()) } != 0
+            || unsafe { libc::setgid(gid) } != 0
+            || unsafe { libc::setuid(uid) } != 0
+        {
+            return Err(Error::last_os_error());
+        }
+        limit_capabilities()?;
+    }
+    Ok(())
+}
+
+/// Answers requests from the workers until toe closes its end of the socket
+fn serve(socket: &OwnedFd, as_owner: bool) {
+    let mut running = HashMap::new();
+    loop {
+        let Ok((request, fd)) = recv(socket) else {
+            return;
+        };
+        if request.is_empty() {
+            return;
+        }
+        let mut stdout = None;
+        let result = match (Request::decode(&request), fd) {
+            (Some(Request::Spawn(request)), Some(program)) => spawn(&request, &program, as_owner)
+                .map(|mut child| {
+                    stdout = child.stdout.take().map(OwnedFd::from);
+                    let pid = libc::pid_t::try_from(child.id()).unwrap_or(0);
+                    running.insert(pid, child);
+                    pid
+                }),
+            (Some(Request::Finish(pid)), None) => finish(pid, &mut running),
+            _ => Err(Error::other("invalid request")),
+        };
+        let fd = stdout.as_ref().map(AsRawFd::as_raw_fd);
+        if send(socket, &reply(&result), fd).is_err() {
+            return;
+        }
+    }
+}
+
+/// A plan program, which is run from the file that was opened when its
+/// symlink policy was checked rather than looked up again by path
+pub struct Program {
+    pub path: PathBuf,
+    file: File,
+}
+
+/// The programs which generate a user's plan, if they may run them: each
+/// executable in `.plan.d` in name order, or else an executable `.plan`
+pub fn programs(cfg: &Config, name: &Username, root: &Path) -> Result<Vec<Program>, Error> {
+    let mut programs = vec![];
+    if !cfg.can_exec(name.as_ref()) {
+        return Ok(programs);
+    }
+    let files = match name.read_dir(root, ".plan.d", cfg.symlinks)? {
+        Some(mut entries) => {
+            entries.retain(|e| !e.starts_with('.'));
+            entries.sort();
+            entries.iter().map(|e| format!(".plan.d/{e}")).collect()
+        }
+        None => vec![".plan".to_string()],
+    };
+    for file in files {
+        if let Some(opened) = name.open(root, &file, cfg.symlinks)? {
+            if opened.metadata()?.permissions().mode() & 0o111 != 0 {
+                programs.push(Program {
+                    path: root.join(name.as_ref()).join(file),
+                    file: opened,
+                });
+            }
+        }
+    }
+    Ok(programs)
+}
+
+/// Sets a resource limit in the child, between fork and exec
+fn limit(resource: libc::__rlimit_resource_t, value: u64) -> Result<(), Error> {
+    let rlim = libc::rlimit {
+        rlim_cur: value,
+        rlim_max: value,
+    };
+    if unsafe { libc::setrlimit(resource, &raw const rlim) } == 0 {
+        Ok(())
+    } else {
+        Err(Error::last_os_error())
+    }
+}
+
+/// Starts a program in the helper, in `request.dir` and as its owner if
+/// `as_owner` is set
+fn spawn(request: &Spawn, program: &OwnedFd, as_owner: bool) -> Result<Child, Error> {
+    let Spawn {
+        cpu_time,
+        memory,
+        processes,
+        ref dir,
+        ref user,
+    } = *request;
+    let owner = if as_owner {
+        let meta = fs::metadata(dir)?;
+        if meta.uid() == 0 {
+            return Err(Error::other(format!("{} is owned by root", dir.display())));
+        }
+        Some((meta.uid(), meta.gid()))
+    } else {
+        None
+    };
+    let fd = program.as_raw_fd();
+    let mut command = Command::new(format!("/proc/self/fd/{fd}"));
+    command
+        .env_clear()
+        .env("PATH", PATH)
+        .env("HOME", dir)
+        .env("USER", user)
+        .env("LOGNAME", user)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    unsafe {
+        command.pre_exec(move || {
+            // Toe blocks SIGHUP and the helper ignores SIGINT and SIGTERM,
+            // either of which would otherwise be inherited
+            let mut set = std::mem::zeroed::<libc::sigset_t>();
+            libc::sigemptyset(&raw mut set);
+            libc::pthread_sigmask(libc::SIG_SETMASK, &raw const set, std::ptr::null_mut());
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::signal(libc::SIGTERM, libc::SIG_DFL);
+            // A session of its own, so that it can be killed along with its
+            // process group
+            if libc::setsid() == -1 {
+                return Err(Error::last_os_error());
+            }
+            limit(libc::RLIMIT_CPU, cpu_time)?;
+            limit(libc::RLIMIT_AS, memory)?;
+            limit(libc::RLIMIT_FSIZE, 0)?;
+            limit(libc::RLIMIT_CORE, 0)?;
+            limit(libc::RLIMIT_NPROC, processes)?;
+            if let Some((uid, gid)) = owner {
+                if libc::setgroups(0, std::ptr::null()) != 0
+                    || libc::setgid(gid) != 0
+                    || libc::setuid(uid) != 0
+                {
+                    return Err(Error::last_os_error());
+                }
+            }
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(Error::last_os_error());
+            }
+            // The program is run through its open file, which must survive
+            // the exec for scripts to be read by their interpreter
+            if libc::fcntl(fd, libc::F_SETFD, 0) != 0 {
+                return Err(Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    command.spawn()
+}
+
+/// Reads a program's output until it closes stdout, it has written more than
+/// `max` bytes, or `deadline` passes. Also returns whether the deadline passed.
+fn read_output(mut stdout: File, max: usize, deadline: Instant) -> Result<(Vec<u8>, bool), Error> {
+    let mut output = vec![];
+    let mut buf = [0; 8192];
+    while output.len() <= max {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok((output, true));
+        }
+        let mut fd = libc::pollfd {
+            fd: stdout.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ms = libc::c_int::try_from(remaining.as_millis()).unwrap_or(libc::c_int::MAX);
+        match unsafe { libc::poll(&raw mut fd, 1, ms.max(1)) } {
+            -1 if Error::last_os_error().kind() == ErrorKind::Interrupted => continue,
+            -1 => return Err(Error::last_os_error()),
+            0 => continue,
+            _ => {}
+        }
+        match stdout.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => output.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((output, false))
+}
+
+/// Each process with its parent and whether it has exited, from `/proc`
+fn processes() -> Vec<(libc::pid_t, libc::pid_t, bool)> {
+    let Ok(procs) = fs::read_dir("/proc") else {
+        return vec![];
+    };
+    procs
+        .flatten()
+        .filter_map(|e| {
+            let pid = e.file_name().to_str()?.parse().ok()?;
+            let stat = fs::read_to_string(e.path().join("stat")).ok()?;
+            let mut fields = stat.rsplit_once(')')?.1.split_whitespace();
+            let zombie = fields.next()? == "Z";
+            Some((pid, fields.next()?.parse().ok()?, zombie))
+        })
+        .collect()
+}
+
+/// Kills a program and everything it started, even processes which have left
+/// its session, then reaps any of them which were handed to the helper
+fn kill_all(pid: libc::pid_t, running: &HashMap<libc::pid_t, Child>) {
+    unsafe {
+        libc::kill(-pid, libc::SIGKILL);
+    }
+    let me = libc::pid_t::try_from(process::id()).unwrap_or(0);
+    for _ in 0..KILL_ROUNDS {
+        let procs = processes();
+        let orphans = procs
+            .iter()
+            .filter(|&&(p, parent, _)| parent == me && p != pid && !running.contains_key(&p))
+            .map(|&(p, ..)| p)
+            .collect::<Vec<_>>();
+        let mut tree = vec![pid];
+        let mut i = 0;
+        while let Some(&parent) = tree.get(i) {
+            tree.extend(
+                procs
+                    .iter()
+                    .filter(|&&(_, pp, zombie)| pp == parent && !zombie)
+                    .map(|&(p, ..)| p),
+            );
+            i += 1;
+        }
+        if orphans.is_empty() && tree.len() == 1 {
+            break;
+        }
+        for &p in orphans.iter().chain(&tree[1..]) {
+            unsafe {
+                libc::kill(p, libc::SIGKILL);
+            }
+        }
+        for &p in &orphans {
+            unsafe {
+                libc::waitpid(p, std::ptr::null_mut(), 0);
+            }
+        }
+    }
+}
+
+/// Kills a program in the helper once its output has been read, returning its
+/// exit status
+fn finish(pid: libc::pid_t, running: &mut HashMap<libc::pid_t, Child>) -> Result<i32, Error> {
+    let Some(mut child) = running.remove(&pid) else {
+        return Err(Error::other(format!("no program is running as {pid}")));
+    };
+    kill_all(pid, running);
+    Ok(child.wait()?.into_raw())
+}
+
+/// Runs a single plan program in `dir`, returning at most `max` bytes of its
+/// stdout. The program and anything it starts are killed at `deadline`.
+pub fn run(
+    cfg: &Exec,
+    program: &Program,
+    dir: &Path,
+    user: &str,
+    deadline: Instant,
+    max: usize,
+) -> Result<Vec<u8>, Error> {
+    let request = Request::Spawn(Spawn {
+        cpu_time: cfg.cpu_time,
+        memory: cfg.memory * 1024 * 1024,
+        processes: cfg.processes,
+        dir: dir.to_path_buf(),
+        user: user.to_string(),
+    });
+    let (pid, stdout) = transact(&request, Some(program.file.as_raw_fd()))?;
+    let result = match stdout {
+        Some(stdout) => read_output(File::from(stdout), max, deadline),
+        None => Err(Error::other("no stdout")),
+    };
+    // Anything still running once its output has been read, or the time is
+    // up, is no longer wanted
+    let (status, _) = transact(&Request::Finish(pid), None)?;
+    let status = ExitStatus::from_raw(status);
+    let (mut output, timed_out) = result?;
+    if timed_out {
+        warning!("{} timed out.", program.path.display());
+        output.truncate(max);
+        return Ok(output);
+    }
+    if output.len() > max {
+        warning!("Output of {} was truncated.", program.path.display());
+        output.truncate(max);
+    } else if let Some(signal) = status.signal().filter(|&s| s != libc::SIGKILL) {
+        warning!("{} was killed by signal {signal}.", program.path.display());
+    } else if !status.success() && status.signal().is_none() {
+        warning!("{} exited with {status}.", program.path.display());
+    }
+    Ok(output)
+}
@@ -0,0 +1,199 @@
+//! The last stage every response passes through before it is sent. As advised
+//! by [RFC 1288 section 3.3](https://datatracker.ietf.org/doc/html/rfc1288#section-3.3),
+//! nothing is relayed which could send commands to the client's terminal:
+//! escape sequences are removed and any other control characters are shown in
+//! caret notation.
+use std::fmt::Write as _;
+
+const ESC: u32 = 0x1b;
+const BEL: u32 = 0x07;
+/// The 8-bit forms of the sequence introducers
+const CSI: u32 = 0x9b;
+const OSC: u32 = 0x9d;
+const DCS: u32 = 0x90;
+const SOS: u32 = 0x98;
+const PM: u32 = 0x9e;
+const APC: u32 = 0x9f;
+/// String terminator
+const ST: u32 = 0x9c;
+
+/// Writes a control character in the caret notation used by `cat -v`
+fn caret(out: &mut Vec<u8>, c: u32) {
+    let mut s = String::new();
+    if c >= 0x80 {
+        s.push_str("M-");
+    }
+    match c & 0x7f {
+        0x7f => s.push_str("^?"),
+        c => _ = write!(s, "^{}", char::from_u32(0x40 + c).unwrap_or('?')),
+    }
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Whether a character is a control which must not reach the client's
+/// terminal. Tabs and line feeds are left alone.
+fn is_control(c: u32) -> bool {
+    (c < 0x20 && c != u32::from(b'\t') && c != u32::from(b'\n')) || (0x7f..=0x9f).contains(&c)
+}
+
+/// Splits the response into characters, each with the bytes it came from.
+/// Bytes which are not part of valid UTF-8 are taken one at a time, so that
+/// bytes 0x80 to 0x9f are seen as the C1 controls they would be to an 8-bit
+/// terminal, while the rest of the response is still read as UTF-8.
+fn units(response: &[u8]) -> Vec<(u32, &[u8])> {
+    let mut units = Vec::with_capacity(response.len());
+    let mut offset = 0;
+    for chunk in response.utf8_chunks() {
+        for (i, c) in chunk.valid().char_indices() {
+            let start = offset + i;
+            units.push((u32::from(c), &response[start..start + c.len_utf8()]));
+        }
+        offset += chunk.valid().len();
+        for i in offset..offset + chunk.invalid().len() {
+            units.push((u32::from(response[i]), &response[i..=i]));
+        }
+        offset += chunk.invalid().len();
+    }
+    units
+}
+
+/// Cleans a response. If `colour` is set, CSI sequences which only select
+/// colours and text styles (SGR) are kept, and if any were kept the styles are
+/// reset at the end, so that none carry over to whatever the client's terminal
+/// shows next.
+pub fn apply(response: &[u8], colour: bool) -> Vec<u8> {
+    let units = units(response);
+    let mut out = Vec::with_capacity(response.len());
+    let mut styled = false;
+    let mut i = 0;
+    while i < units.len() {
+        let (c, bytes) = units[i];
+        let next = units.get(i + 1).map(|u| u.0);
+        i += 1;
+        match (c, next) {
+            (0x0d, Some(0x0a)) => {}
+            (ESC, Some(0x5b)) | (CSI, _) => {
+                if c == ESC {
+                    i += 1;
+                }
+                let start = i;
+                // Parameter and intermediate bytes, then the final byte
+                while units.get(i).is_some_and(|u| (0x20..=0x3f).contains(&u.0)) {
+                    i += 1;
+                }
+                let end = i;
+                let Some(&(last, _)) = units.get(i) else {
+                    break;
+                };
+                if (0x40..=0x7e).contains(&last) {
+                    i += 1;
+                }
+                let sgr = last == u32::from(b'm')
+                    && units[start..end]
+                        .iter()
+                        .all(|u| u.0 == u32::from(b';') || (0x30..=0x39).contains(&u.0));
+                if colour && sgr {
+                    styled = true;
+                    out.extend_from_slice(b"\x1b[");
+                    for (_, bytes) in &units[start..=end] {
+                        out.extend_from_slice(bytes);
+                    }
+                }
+            }
+            (ESC, Some(0x5d | 0x50 | 0x58 | 0x5e | 0x5f)) | (OSC | DCS | SOS | PM | APC, _) => {
+                if c == ESC {
+                    i += 1;
+                }
+                // Everything up to and including the terminator is dropped
+                while let Some(&(c, _)) = units.get(i) {
+                    i += 1;
+                    if c == BEL || c == ST {
+                        break;
+                    }
+                    if c == ESC && units.get(i).is_some_and(|u| u.0 == u32::from(b'\\')) {
+                        i += 1;
+                        break;
+                    }
+                }
+            }
+            (ESC, _) => {
+                // Any other escape sequence: intermediate bytes, then the
+                // final byte
+                while units.get(i).is_some_and(|u| (0x20..=0x2f).contains(&u.0)) {
+                    i += 1;
+                }
+                if units.get(i).is_some_and(|u| (0x30..=0x7e).contains(&u.0)) {
+                    i += 1;
+                }
+            }
+            (c, _) if is_control(c) => caret(&mut out, c),
+            _ => out.extend_from_slice(bytes),
+        }
+    }
+    if styled {
+        out.extend_from_slice(b"\x1b[0m");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain() {
+        assert_eq!(apply(b"plan\n\ttabbed\n", false), b"plan\n\ttabbed\n");
+        assert_eq!(apply("café\n".as_bytes(), false), "café\n".as_bytes());
+    }
+
+    #[test]
+    fn line_endings() {
+        assert_eq!(apply(b"a\r\nb\r", false), b"a\nb^M");
+    }
+
+    #[test]
+    fn controls() {
+        assert_eq!(apply(b"bell\x07 del\x7f", false), b"bell^G del^?");
+        assert_eq!(apply("c1\u{85}".as_bytes(), false), b"c1M-^E");
+    }
+
+    #[test]
+    fn csi() {
+        assert_eq!(apply(b"a\x1b[2Jb\x1b[1;1Hc", false), b"abc");
+        assert_eq!(apply(b"a\x1b[?25lb", false), b"ab");
+        // Cut off before the final byte
+        assert_eq!(apply(b"a\x1b[12", false), b"a");
+    }
+
+    #[test]
+    fn osc() {
+        assert_eq!(apply(b"a\x1b]0;title\x07b", false), b"ab");
+        assert_eq!(apply(b"a\x1b]8;;http://x\x1b\\b", false), b"ab");
+        assert_eq!(apply(b"a\x1bPdata\x1b\\b", false), b"ab");
+    }
+
+    #[test]
+    fn other_escapes() {
+        assert_eq!(apply(b"a\x1bcb\x1b(0c", false), b"abc");
+    }
+
+    #[test]
+    fn eight_bit() {
+        // The 8-bit CSI, both encoded as UTF-8 and as a bare byte in a
+        // response which is otherwise UTF-8
+        assert_eq!(apply("é\u{9b}2Jx".as_bytes(), false), "éx".as_bytes());
+        assert_eq!(apply(b"\xc3\xa9\x9b2Jx", false), "éx".as_bytes());
+        assert_eq!(apply(b"a\x9d0;title\x9cb", false), b"ab");
+        assert_eq!(apply(b"a\x85b\xff", false), b"aM-^Eb\xff");
+    }
+
+    #[test]
+    fn sgr() {
+        let styled = b"\x1b[1;31mred\x1b[0m\n";
+        assert_eq!(apply(styled, false), b"red\n");
+        assert_eq!(apply(styled, true), b"\x1b[1;31mred\x1b[0m\n\x1b[0m");
+        // Only SGR is kept
+        assert_eq!(apply(b"\x1b[2J\x1b[?1m", true), b"");
+        assert_eq!(apply(b"no style\n", true), b"no style\n");
+    }
+}
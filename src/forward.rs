@@ -0,0 +1,116 @@
+//! Forwarding of `{U}{H}` queries on to other hosts, as described in
+//! [RFC 1288 section 2.5.1](https://datatracker.ietf.org/doc/html/rfc1288#section-2.5.1)
+use {
+    crate::{config::Forward, connection::remaining},
+    std::{
+        io::{Error, ErrorKind, Read, Write},
+        net::{TcpStream, ToSocketAddrs},
+        time::Instant,
+    },
+};
+
+/// The text which RFC 1288 recommends returning when refusing to forward
+pub const DENIED: &str = "Finger forwarding service denied\n";
+
+/// The maximum number of bytes which will be relayed back from an upstream host
+const MAX_RESPONSE: u64 = 64 * 1024;
+
+/// The reason that a forwarded query was refused
+#[derive(Debug, PartialEq, Eq)]
+pub enum Refusal {
+    /// Forwarding is turned off in the config
+    Disabled,
+    /// The query passes through more hosts than `max_hops`
+    TooManyHops,
+    /// The next host is not in the allow list
+    NotAllowed(String),
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Disabled => write!(f, "forwarding is disabled"),
+            Self::TooManyHops => write!(f, "too many hops"),
+            Self::NotAllowed(host) => write!(f, "host {host} is not in the allow list"),
+        }
+    }
+}
+
+/// Checks a forwarded query against the configured policy, returning the host
+/// which the query should be sent on to
+pub fn check<'a>(cfg: &Forward, hosts: &'a [String]) -> Result<&'a str, Refusal> {
+    if !cfg.enabled {
+        return Err(Refusal::Disabled);
+    }
+    if hosts.len() > cfg.max_hops {
+        return Err(Refusal::TooManyHops);
+    }
+    let Some(next) = hosts.last() else {
+        return Err(Refusal::TooManyHops);
+    };
+    if cfg.allow.iter().any(|x| x.eq_ignore_ascii_case(next)) {
+        Ok(next)
+    } else {
+        Err(Refusal::NotAllowed(next.clone()))
+    }
+}
+
+/// Sends the query on to `next`, stripping its hostname from the end of the
+/// query, and returns the upstream response. Gives up at `deadline`, whatever
+/// the configured timeouts.
+pub fn forward(
+    cfg: &Forward,
+    next: &str,
+    user: Option<&str>,
+    hosts: &[String],
+    verbose: bool,
+    deadline: Instant,
+) -> Result<Vec<u8>, Error> {
+    let mut query = String::new();
+    if verbose {
+        query.push_str("/W ");
+    }
+    if let Some(user) = user {
+        query.push_str(user);
+    }
+    for host in &hosts[..hosts.len().saturating_sub(1)] {
+        query.push('@');
+        query.push_str(host);
+    }
+    query.push_str("\r\n");
+    let mut stream = connect(cfg, next, deadline)?;
+    stream.set_write_timeout(Some(remaining(cfg.read_timeout, deadline)?))?;
+    stream.write_all(query.as_bytes())?;
+    let mut response = vec![];
+    let mut buf = [0; 4096];
+    let max = usize::try_from(MAX_RESPONSE).unwrap_or(usize::MAX);
+    while response.len() < max {
+        stream.set_read_timeout(Some(remaining(cfg.read_timeout, deadline)?))?;
+        let len = match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "timed out waiting for response",
+                ));
+            }
+            Err(e) => return Err(e),
+        };
+        response.extend_from_slice(&buf[..len.min(max - response.len())]);
+    }
+    Ok(response)
+}
+
+fn connect(cfg: &Forward, host: &str, deadline: Instant) -> Result<TcpStream, Error> {
+    let mut err = Error::new(ErrorKind::NotFound, format!("Unable to resolve {host}"));
+    for addr in (host, 79).to_socket_addrs()? {
+        let timeout = remaining(cfg.connect_timeout, deadline)?;
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(s) => return Ok(s),
+            Err(e) => err = e,
+        }
+    }
+    Err(err)
+}
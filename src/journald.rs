@@ -0,0 +1,50 @@
+//! A minimal client for the systemd journal's native protocol. As with syslog,
+//! the socket is connected at startup, before entering the chroot.
+use {
+    crate::syslog::Severity,
+    std::{io::Error, os::unix::net::UnixDatagram, process},
+};
+
+/// The path of the journal's native socket
+const SOCKET: &str = "/run/systemd/journal/socket";
+
+pub struct Journald {
+    socket: UnixDatagram,
+    pid: u32,
+}
+
+/// Appends a single field to a journal entry. Values containing a newline
+/// must use the length prefixed binary form.
+fn field(buf: &mut Vec<u8>, key: &str, value: &str) {
+    buf.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(b'\n');
+}
+
+impl Journald {
+    pub fn connect() -> Result<Self, Error> {
+        let socket = UnixDatagram::unbound()?;
+        socket
+            .connect(SOCKET)
+            .map_err(|e| Error::new(e.kind(), format!("{SOCKET}: {e}")))?;
+        Ok(Self {
+            socket,
+            pid: process::id(),
+        })
+    }
+
+    pub fn send(&self, severity: Severity, msg: &str) -> Result<(), Error> {
+        let mut buf = Vec::with_capacity(msg.len() + 64);
+        field(&mut buf, "PRIORITY", &(severity as u8).to_string());
+        field(&mut buf, "SYSLOG_IDENTIFIER", "toe");
+        field(&mut buf, "SYSLOG_PID", &self.pid.to_string());
+        field(&mut buf, "MESSAGE", msg);
+        self.socket.send(&buf).map(|_| ())
+    }
+}
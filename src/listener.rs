@@ -0,0 +1,94 @@
+//! Creation of listening sockets. The standard library offers no way to set
+//! socket options before binding, which we need in order to bind both
+//! `0.0.0.0` and `[::]` on the same port, so the sockets are created by hand.
+use std::{
+    io::Error,
+    mem,
+    net::{SocketAddr, TcpListener},
+    os::unix::io::{FromRawFd, OwnedFd, RawFd},
+};
+
+/// The length of the queue of pending connections
+const BACKLOG: libc::c_int = 128;
+
+// Socket structure sizes and address family constants always fit their C types
+#[allow(clippy::cast_possible_truncation)]
+fn setsockopt(fd: RawFd, level: libc::c_int, name: libc::c_int) -> Result<(), Error> {
+    let val: libc::c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            (&raw const val).cast(),
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(Error::last_os_error())
+    }
+}
+
+/// Binds a listening socket to `addr`. IPv6 sockets are set to only accept
+/// IPv6 connections, so that IPv4 is served by its own listener.
+#[allow(clippy::cast_possible_truncation)]
+pub fn bind(addr: SocketAddr) -> Result<TcpListener, Error> {
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(domain, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(Error::last_os_error());
+    }
+    // Take ownership immediately so that the socket is closed on error
+    let owned = unsafe { OwnedFd::from_raw_fd(fd) };
+    setsockopt(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR)?;
+    let ret = match addr {
+        SocketAddr::V4(a) => {
+            let sin = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: a.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from_ne_bytes(a.ip().octets()),
+                },
+                sin_zero: [0; 8],
+            };
+            unsafe {
+                libc::bind(
+                    fd,
+                    (&raw const sin).cast(),
+                    mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                )
+            }
+        }
+        SocketAddr::V6(a) => {
+            setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY)?;
+            let sin6 = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: a.port().to_be(),
+                sin6_flowinfo: a.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: a.ip().octets(),
+                },
+                sin6_scope_id: a.scope_id(),
+            };
+            unsafe {
+                libc::bind(
+                    fd,
+                    (&raw const sin6).cast(),
+                    mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                )
+            }
+        }
+    };
+    if ret != 0 {
+        return Err(Error::last_os_error());
+    }
+    if unsafe { libc::listen(fd, BACKLOG) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(TcpListener::from(owned))
+}
@@ -0,0 +1,114 @@
+//! Server messages, sent to stderr, syslog or the systemd journal depending on
+//! the `[log]` section of the config. Until `open` is called, and if writing
+//! to the chosen backend fails, messages go to stderr.
+use {
+    crate::{
+        config::{self, Backend, Level},
+        journald::Journald,
+        syslog::{Severity, Syslog},
+    },
+    std::{
+        fmt,
+        io::{self, Error, Write},
+        sync::{
+            atomic::{AtomicU8, Ordering},
+            OnceLock,
+        },
+    },
+};
+
+static SINK: OnceLock<Sink> = OnceLock::new();
+
+/// The least severe level which is still logged
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+enum Sink {
+    Stderr,
+    Syslog(Syslog),
+    Journald(Journald),
+}
+
+impl From<Level> for Severity {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Error => Self::Error,
+            Level::Warn => Self::Warning,
+            Level::Info => Self::Info,
+            Level::Debug => Self::Debug,
+        }
+    }
+}
+
+/// Connects to the configured backend. This must be done before entering the
+/// chroot.
+pub fn open(cfg: &config::Log) -> Result<(), Error> {
+    let sink = match cfg.backend {
+        Backend::Stderr => Sink::Stderr,
+        Backend::Syslog => Sink::Syslog(Syslog::connect()?),
+        Backend::Journald => Sink::Journald(Journald::connect()?),
+    };
+    _ = SINK.set(sink);
+    set_level(cfg.level);
+    Ok(())
+}
+
+/// Changes the level, which unlike the backend may be changed on reload
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+fn stderr(level: Level, msg: &str) {
+    let prefix = match level {
+        Level::Error => "error: ",
+        Level::Warn => "warning: ",
+        Level::Info => "",
+        Level::Debug => "debug: ",
+    };
+    _ = writeln!(io::stderr(), "{prefix}{msg}");
+}
+
+/// Logs a message, if `level` is severe enough. Use the `error!`, `warning!`,
+/// `info!` and `debug!` macros rather than calling this directly.
+pub fn log(level: Level, args: fmt::Arguments<'_>) {
+    if level as u8 > LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+    let msg = args.to_string();
+    let res = match SINK.get() {
+        None | Some(Sink::Stderr) => {
+            stderr(level, &msg);
+            Ok(())
+        }
+        Some(Sink::Syslog(s)) => s.send(level.into(), &msg),
+        Some(Sink::Journald(j)) => j.send(level.into(), &msg),
+    };
+    if res.is_err() {
+        stderr(level, &msg);
+    }
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::config::Level::Error, format_args!($($arg)*))
+    };
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::config::Level::Warn, format_args!($($arg)*))
+    };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::config::Level::Info, format_args!($($arg)*))
+    };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::config::Level::Debug, format_args!($($arg)*))
+    };
+}
+
+pub(crate) use {debug, error, info, warning};
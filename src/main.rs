@@ -1,294 +1,734 @@
 #![warn(clippy::all, clippy::pedantic)]
+mod access;
+mod accesslog;
+mod activation;
+mod cache;
+mod cli;
+mod collector;
 mod config;
+mod connection;
+mod content;
+mod exec;
+mod filter;
+mod forward;
+mod journald;
+mod listener;
+mod log;
+mod passwd;
+mod ratelimit;
+mod reload;
+mod request;
+mod stats;
+mod syslog;
+mod template;
 mod threadpool;
 mod time;
+mod username;
+mod utmp;
 
 use {
-    chrono::Timelike,
-    config::{Config, Stats},
-    lazy_static::lazy_static,
+    accesslog::Outcome,
+    chrono::TimeZone,
+    cli::Args,
+    config::{Config, Destination, Overload, Stats},
+    log::{debug, error, info, warning},
+    passwd::Passwd,
+    reload::ConfigFile,
+    request::Request,
     std::{
         env,
         fmt::Write as _,
         fs,
-        io::{Error, ErrorKind, Read, Write},
-        net::{TcpListener, TcpStream},
-        num::NonZeroUsize,
+        io::{Error, ErrorKind, Write},
+        net::{IpAddr, SocketAddr, TcpListener, TcpStream},
         os::unix,
-        path::PathBuf,
+        path::Path,
         process,
-        sync::{mpsc::channel, Arc, Mutex},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            mpsc::{channel, RecvTimeoutError},
+            Arc, LazyLock, Mutex, RwLock,
+        },
         thread,
+        time::{Duration, Instant},
     },
-    sysinfo::{Component, ComponentExt, System, SystemExt},
+    sysinfo::SystemExt,
     threadpool::ThreadPool,
     time::Time,
+    username::Username,
+    utmp::LastLogin,
 };
 
-lazy_static! {
-    static ref CONFIG: Config = match Config::load() {
-        Ok(c) => c,
+static ARGS: LazyLock<Args> = LazyLock::new(Args::parse);
+
+static CONFIG: LazyLock<RwLock<Arc<Config>>> = LazyLock::new(|| {
+    let config = Config::load(&ARGS.config).and_then(|mut c| {
+        ARGS.apply(&mut c);
+        let dir = ARGS.config.parent().unwrap_or(Path::new(""));
+        c.load_template(|path| fs::read_to_string(dir.join(path)))?;
+        c.validate()?;
+        c.check_root().map(|()| c)
+    });
+    match config {
+        Ok(c) => RwLock::new(Arc::new(c)),
         Err(e) => {
-            eprintln!("Unable to load config: {e}");
+            error!("Unable to load config: {e}");
             process::exit(1);
         }
-    };
-    static ref SYS: Mutex<System> = Mutex::new(System::new_all());
+    }
+});
+
+/// The reply sent to connections rejected when the queue is full
+const BUSY: &[u8] = b"Server busy, please try again later.\n";
+
+/// How long a listener waits after failing to accept a connection, so that an
+/// error which persists, such as running out of file descriptors, does not
+/// keep it spinning
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The number of connections which have been rejected due to a full queue
+static REJECTED: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the config which is currently in effect
+fn config() -> Arc<Config> {
+    Arc::clone(&CONFIG.read().unwrap())
+}
+
+/// Re-reads the config file, replacing the running config if it is valid
+fn reload(file: &ConfigFile) {
+    let running = config();
+    let config = file
+        .read()
+        .and_then(|raw| Config::from_toml(&raw, file.path()))
+        .and_then(|mut c| {
+            ARGS.apply(&mut c);
+            for key in c.keep_restart_settings(&running) {
+                warning!("Setting `{key}` has changed, restart toe to apply it.");
+            }
+            c.load_template(|path| file.read_relative(path))?;
+            c.validate().map(|()| c)
+        });
+    match config {
+        Ok(c) => {
+            log::set_level(c.log.level);
+            if !c.cache.enabled
+                || c.cache.max_size < running.cache.max_size
+                || c.content.max_size != running.content.max_size
+                || c.symlinks != running.symlinks
+            {
+                cache::clear();
+            }
+            cache::report();
+            *CONFIG.write().unwrap() = Arc::new(c);
+            info!("Config reloaded from {}.", file.path().display());
+        }
+        Err(e) => error!("Unable to reload config, keeping current settings: {e}"),
+    }
 }
 
-fn privdrop(user: *mut libc::passwd, group: *mut libc::group) -> std::io::Result<()> {
+fn privdrop(cfg: &Config, user: *mut libc::passwd, group: *mut libc::group) -> std::io::Result<()> {
     if unsafe { libc::setgid((*group).gr_gid) } != 0 {
-        eprintln!("privdrop: Unable to setgid of group: {}", &CONFIG.group);
+        error!("privdrop: Unable to setgid of group: {}", &cfg.group);
         return Err(Error::last_os_error());
     }
     if unsafe { libc::setuid((*user).pw_uid) } != 0 {
-        eprintln!("privdrop: Unable to setuid of user: {}", &CONFIG.user);
+        error!("privdrop: Unable to setuid of user: {}", &cfg.user);
         return Err(Error::last_os_error());
     }
     Ok(())
 }
 
-fn users() -> Vec<PathBuf> {
-    let mut paths = vec![];
-    let root = if CONFIG.chroot {
-        PathBuf::from("/")
+fn server_info(
+    cfg: &Config,
+    peer: IpAddr,
+    verbose: bool,
+    deadline: Instant,
+) -> Result<Vec<u8>, std::fmt::Error> {
+    let sysinfo = if let Some(layout) = &cfg.layout {
+        layout.render(cfg)?
     } else {
-        PathBuf::from(&CONFIG.root)
+        default_layout(cfg)?
     };
-    if let Ok(dir) = fs::read_dir(root) {
-        for entry in dir.flatten() {
-            let mut path = entry.path();
-            path.push(".plan");
-            paths.push(path);
+    let mut sysinfo = sysinfo.into_bytes();
+    // Users' own output is only added for clients which could have asked
+    // for it directly
+    if verbose && cfg.access.user.permits(peer) {
+        // Every user's plan programs share a single timeout, so a list costs
+        // no more than a query for one user
+        let plans = plan_deadline(cfg, deadline);
+        for name in stats::users(cfg) {
+            if Instant::now() >= deadline {
+                break;
+            }
+            if let Ok(Some(info)) = user_response(cfg, &name, true, plans) {
+                sysinfo.push(b'\n');
+                sysinfo.extend(info);
+            }
         }
     }
-    paths
+    Ok(sysinfo)
 }
 
-fn kernel_info(mut sysinfo: String) -> Result<String, std::fmt::Error> {
-    let sys = SYS.lock().unwrap();
-    if let Some(name) = sys.name() {
-        write!(sysinfo, "{name} ")?;
-    }
-    if let Some(kern) = sys.kernel_version() {
-        write!(sysinfo, "{kern} ")?;
-    }
-    if let Some(os) = sys.os_version() {
-        write!(sysinfo, "{os} ")?;
+/// The server info page used when no template is configured
+fn default_layout(cfg: &Config) -> Result<String, std::fmt::Error> {
+    let mut sysinfo = format!("{}\n", cfg.server);
+    for _ in 0..cfg.server.len() {
+        write!(sysinfo, "=")?;
     }
     write!(sysinfo, "\n\n")?;
+    for stat in [
+        Stats::Kernel,
+        Stats::Users,
+        Stats::Listing,
+        Stats::Uptime,
+        Stats::Cpu,
+        Stats::Memory,
+        Stats::Swap,
+        Stats::Disks,
+        Stats::Network,
+        Stats::Sensors,
+    ] {
+        if cfg.stats.contains(&stat) {
+            sysinfo = stats::render(cfg, stat, sysinfo)?;
+        }
+    }
     Ok(sysinfo)
 }
 
-fn user_info(mut sysinfo: String) -> Result<String, std::fmt::Error> {
-    write!(sysinfo, "Users: ")?;
-    for path in &users() {
-        if path.exists() {
-            if let Some(name) = path.to_string_lossy().split('/').nth(1) {
-                write!(sysinfo, " {name}")?;
+/// Formats a login session as shown in the user header
+fn session(prefix: &str, session: &utmp::Session) -> String {
+    let mut line = String::from(prefix);
+    if let Some(time) = chrono::Local.timestamp_opt(session.time, 0).single() {
+        _ = write!(line, " {}", time.format("%a %b %e %H:%M (%Z)"));
+    }
+    if !session.line.is_empty() {
+        _ = write!(line, " on {}", session.line);
+    }
+    if !session.host.is_empty() {
+        _ = write!(line, " from {}", session.host);
+    }
+    line
+}
+
+/// Finds the last login of a user in the configured wtmp and lastlog files
+fn last_login(cfg: &Config, name: &Username, passwd: Option<&Passwd>) -> Option<LastLogin> {
+    let header = &cfg.header;
+    let mut last = None;
+    if !header.wtmp.is_empty() {
+        match utmp::last_login(&cfg.root_path(&header.wtmp), name.as_ref()) {
+            Ok(l) => last = Some(l),
+            Err(e) => debug!("Unable to read {}: {e}", header.wtmp),
+        }
+    }
+    // The wtmp file may have been rotated since the user's last login
+    if matches!(last, None | Some(LastLogin::Never)) && !header.lastlog.is_empty() {
+        if let Some(passwd) = passwd {
+            match utmp::lastlog(&cfg.root_path(&header.lastlog), name.as_ref(), passwd.uid) {
+                Ok(l) => last = Some(l),
+                Err(e) => debug!("Unable to read {}: {e}", header.lastlog),
             }
         }
     }
-    write!(sysinfo, "\n\n")?;
-    Ok(sysinfo)
+    last
 }
 
-fn uptime_info(mut sysinfo: String) -> Result<String, std::fmt::Error> {
-    let mut sys = SYS.lock().unwrap();
-    write!(sysinfo, "System Status\n-------------\n\n")?;
-    sys.refresh_all();
-    let current = chrono::Utc::now();
-    let uptime = Time::uptime(&sys);
-    let users = sys.users().len();
-    let load = sys.load_average();
-    write!(
-        sysinfo,
-        "{:02}:{:02}:{:02} up {} days {:02}:{:02}, {users} users, load average {} {} {}\n\n",
-        current.hour(),
-        current.minute(),
-        current.second(),
-        uptime.days(),
-        uptime.hours(),
-        uptime.minutes(),
-        load.one,
-        load.five,
-        load.fifteen,
-    )?;
-    Ok(sysinfo)
+/// Builds the header shown above a user's files, with the fields enabled in
+/// the `[header]` section
+fn user_header(cfg: &Config, name: &Username) -> String {
+    let header = &cfg.header;
+    let passwd = match passwd::lookup(&cfg.root_path(&header.passwd), name.as_ref()) {
+        Ok(p) => p,
+        Err(e) => {
+            debug!("Unable to read {}: {e}", header.passwd);
+            None
+        }
+    };
+    let mut fields = vec![];
+    if header.login {
+        fields.push(format!("Login: {name}"));
+    }
+    if let Some(passwd) = &passwd {
+        if header.name && !passwd.full_name.is_empty() {
+            fields.push(format!("Name: {}", passwd.full_name));
+        }
+        if header.directory && !passwd.dir.is_empty() {
+            fields.push(format!("Directory: {}", passwd.dir));
+        }
+        if header.shell && !passwd.shell.is_empty() {
+            fields.push(format!("Shell: {}", passwd.shell));
+        }
+    }
+    let mut out = String::new();
+    // Two fields to a line, as the classic fingerd does
+    for pair in fields.chunks(2) {
+        match pair {
+            [left, right] => _ = writeln!(out, "{left:<39} {right}"),
+            [left] => _ = writeln!(out, "{left}"),
+            _ => {}
+        }
+    }
+    if header.last_login {
+        match last_login(cfg, name, passwd.as_ref()) {
+            Some(LastLogin::Active(s)) => _ = writeln!(out, "{}", session("On since", &s)),
+            Some(LastLogin::Ended(s)) => _ = writeln!(out, "{}", session("Last login", &s)),
+            Some(LastLogin::Never) => _ = writeln!(out, "Never logged in."),
+            None => {}
+        }
+    }
+    out
 }
 
-fn cpu_info(mut sysinfo: String) -> Result<String, std::fmt::Error> {
-    let sys = SYS.lock().unwrap();
-    let mut components: Vec<&Component> = sys.components().iter().collect();
-    components.iter().try_for_each(|x| {
-        if x.label().starts_with("coretemp Core") {
-            writeln!(
-                sysinfo,
-                "{}: +{}°C  (max = +{}°C, critical = +{}°C)",
-                &x.label().replace("coretemp ", ""),
-                &x.temperature(),
-                &x.max(),
-                &x.critical().unwrap_or_else(|| x.max()),
-            )
-        } else if x.label().starts_with("cpu_thermal temp") {
-            writeln!(
-                sysinfo,
-                "{}: +{}°C  (max = +{}°C, critical = +{}°C)",
-                &x.label().replace("cpu_thermal temp", "Core "),
-                &x.temperature(),
-                &x.max(),
-                &x.critical().unwrap_or_else(|| x.max()),
-            )
-        } else {
-            Ok(())
+/// The time by which plan programs must finish: `exec.timeout` from now, or
+/// `deadline` if that is sooner
+fn plan_deadline(cfg: &Config, deadline: Instant) -> Instant {
+    deadline.min(Instant::now() + Duration::from_secs(cfg.exec.timeout))
+}
+
+/// Runs the programs which generate a user's plan, returning `None` if there
+/// are none. The programs are stopped at `deadline`, and none are started
+/// once it has passed, leaving the output empty.
+fn plan_output(
+    cfg: &Config,
+    name: &Username,
+    root: &Path,
+    deadline: Instant,
+) -> std::io::Result<Option<Vec<u8>>> {
+    let programs = exec::programs(cfg, name, root)?;
+    if programs.is_empty() {
+        return Ok(None);
+    }
+    let dir = root.join(name.as_ref());
+    let mut output = vec![];
+    for program in programs {
+        if Instant::now() >= deadline {
+            break;
+        }
+        let max = cfg.exec.max_output - output.len();
+        match exec::run(&cfg.exec, &program, &dir, name.as_ref(), deadline, max) {
+            Ok(out) => output.extend(out),
+            Err(e) => {
+                warning!("Unable to run plan for {name}: {e}");
+                break;
+            }
+        }
+        if output.len() >= cfg.exec.max_output {
+            break;
         }
-    })?;
-    let mut cores = components.clone();
-    cores.retain(|x| x.label().starts_with("Core"));
-    if cores.is_empty() {
-        components.retain(|x| x.label().starts_with("CPU"));
-        components.iter().try_for_each(|x| {
-            writeln!(
-                sysinfo,
-                "{}:       +{}°C  (max = +{}°C, critical = +{}°C)",
-                &x.label(),
-                &x.temperature(),
-                &x.max(),
-                &x.critical().unwrap_or_else(|| x.max()),
-            )
-        })?;
-    } else {
-        cores.iter().try_for_each(|x| {
-            writeln!(
-                sysinfo,
-                "{}:       +{}°C  (max = +{}°C, critical = +{}°C)",
-                &x.label(),
-                &x.temperature(),
-                &x.max(),
-                &x.critical().unwrap_or_else(|| x.max()),
-            )
-        })?;
     }
-    Ok(sysinfo)
+    Ok(Some(output))
 }
 
-fn server_info() -> Result<String, std::fmt::Error> {
-    let mut sysinfo = format!("{}\n", CONFIG.server);
-    for _ in 0..CONFIG.server.len() {
-        write!(sysinfo, "=")?;
+/// Returns the response for a query about a single user, or `None` if the user
+/// does not exist or is not sharing any files. Plan programs are stopped at
+/// `deadline`.
+fn user_response(
+    cfg: &Config,
+    name: &Username,
+    verbose: bool,
+    deadline: Instant,
+) -> std::io::Result<Option<Vec<u8>>> {
+    let root = cfg.server_root();
+    let colour = cfg.allows_colour(name.as_ref());
+    let mut sections = vec![];
+    for file in cfg.user_files(name.as_ref(), verbose) {
+        if file.name == ".plan" {
+            if let Some(output) = plan_output(cfg, name, &root, deadline)? {
+                sections.push((
+                    &file.heading,
+                    content::prepare(&cfg.content, &output, colour),
+                ));
+                continue;
+            }
+        }
+        if let Some(contents) = cache::read(cfg, name, &file.name, &root)? {
+            sections.push((
+                &file.heading,
+                content::prepare(&cfg.content, &contents, colour),
+            ));
+        }
     }
-    write!(sysinfo, "\n\n")?;
-    if CONFIG.stats.contains(&Stats::Kernel) {
-        sysinfo = kernel_info(sysinfo)?;
+    if sections.is_empty() {
+        return Ok(None);
     }
-    if CONFIG.stats.contains(&Stats::Users) {
-        sysinfo = user_info(sysinfo)?;
+    // A single file in the short form is shown bare, as it always has been
+    let headings = verbose || sections.len() > 1;
+    let mut response = if verbose || !cfg.header.verbose_only {
+        user_header(cfg, name).into_bytes()
+    } else {
+        vec![]
+    };
+    if !response.is_empty() {
+        response.push(b'\n');
     }
-    if CONFIG.stats.contains(&Stats::Uptime) {
-        sysinfo = uptime_info(sysinfo)?;
+    for (i, (heading, contents)) in sections.iter().enumerate() {
+        if i > 0 {
+            response.push(b'\n');
+        }
+        if headings && !heading.is_empty() {
+            writeln!(response, "{heading}")?;
+        }
+        response.extend_from_slice(contents);
+        if !contents.ends_with(b"\n") {
+            response.push(b'\n');
+        }
     }
-    if CONFIG.stats.contains(&Stats::Cpu) {
-        sysinfo = cpu_info(sysinfo)?;
+    response.push(b'\n');
+    Ok(Some(response))
+}
+
+/// Builds the response to a single query, logging the outcome. Any work which
+/// may take a while is abandoned at `deadline`.
+fn respond(
+    cfg: &Config,
+    from: &str,
+    peer: IpAddr,
+    query: &str,
+    deadline: Instant,
+) -> std::io::Result<(Outcome, Vec<u8>)> {
+    let request = match query.parse::<Request>() {
+        Ok(r) => r,
+        Err(e) => {
+            warning!("{from}: Malformed request: {e}");
+            return Ok((Outcome::Malformed, b"Malformed request\n".to_vec()));
+        }
+    };
+    let rules = match request {
+        Request::List { .. } => &cfg.access.info,
+        Request::User { .. } => &cfg.access.user,
+        Request::Forward { .. } => &cfg.access.forward,
+    };
+    if !rules.permits(peer) {
+        warning!("{from}: Access denied for request `{request}`.");
+        return Ok((Outcome::Denied, b"Access denied\n".to_vec()));
     }
-    Ok(sysinfo)
+    // Colour is allowed for the whole response to a query for the user,
+    // including the header which toe writes above their files
+    let colour = matches!(&request, Request::User { name, .. } if cfg.allows_colour(name));
+    let (outcome, response) = answer(cfg, from, peer, request, deadline)?;
+    Ok((outcome, filter::apply(&response, colour)))
 }
 
-fn handle_connection(mut stream: TcpStream) -> std::io::Result<()> {
-    let mut buf = vec![0; 1024];
-    let _len = stream.read(&mut buf)?;
-    let request = String::from_utf8(buf).unwrap();
-    let request = request.trim_matches(char::from(0)).trim();
-    if request.contains(char::is_whitespace) {
-        _ = stream.write(b"Malformed response\n")?;
-        return Err(Error::new(ErrorKind::Other, "Malformed response"));
-    }
-    if request.is_empty() {
-        match server_info() {
+/// Builds the response to a query which has been permitted, before it is
+/// filtered
+fn answer(
+    cfg: &Config,
+    from: &str,
+    peer: IpAddr,
+    request: Request,
+    deadline: Instant,
+) -> std::io::Result<(Outcome, Vec<u8>)> {
+    match request {
+        Request::List { verbose } => match server_info(cfg, peer, verbose, deadline) {
             Ok(info) => {
-                println!("Serving system info request");
-                _ = stream.write(info.as_bytes())?;
+                info!("{from}: Serving system info request");
+                Ok((Outcome::Info, info))
+            }
+            Err(e) => Err(Error::other(format!("{from}: {e}"))),
+        },
+        Request::User { name, verbose } => {
+            let output = match name.parse::<Username>() {
+                Ok(user) => user_response(cfg, &user, verbose, plan_deadline(cfg, deadline))?,
+                Err(e) => {
+                    warning!("{from}: Invalid username requested: {e}.");
+                    None
+                }
+            };
+            if let Some(output) = output {
+                info!("{from}: Serving info for user {name}.");
+                Ok((Outcome::User, output))
+            } else {
+                info!("{from}: Request for unknown user {name}.");
+                Ok((
+                    Outcome::Unknown,
+                    format!("{name}'s not here man.\n").into_bytes(),
+                ))
+            }
+        }
+        Request::Forward {
+            ref user,
+            ref hosts,
+            verbose,
+        } => match forward::check(&cfg.forward, hosts) {
+            Ok(next) => {
+                info!("{from}: Forwarding request {request} to {next}.");
+                let upstream = user.as_deref();
+                match forward::forward(&cfg.forward, next, upstream, hosts, verbose, deadline) {
+                    Ok(output) => Ok((Outcome::Forwarded, output)),
+                    Err(e) => {
+                        warning!("{from}: Unable to forward request to {next}: {e}");
+                        Ok((
+                            Outcome::Error,
+                            format!("Unable to reach {next}.\n").into_bytes(),
+                        ))
+                    }
+                }
             }
             Err(e) => {
-                eprintln!("{e}");
-                return Err(Error::new(ErrorKind::Other, format!("{e}")));
+                warning!("{from}: Refusing to forward request {request}: {e}.");
+                Ok((Outcome::Refused, forward::DENIED.as_bytes().to_vec()))
             }
-        };
+        },
+    }
+}
+
+/// Handles a single connection which arrived on the listener bound to `local`
+fn handle_connection(mut stream: TcpStream, local: SocketAddr) -> std::io::Result<()> {
+    let start = Instant::now();
+    let cfg = config();
+    let deadline = start + Duration::from_secs(cfg.connection.deadline);
+    let peer = stream.peer_addr()?.ip().to_canonical();
+    let from = format!("[{local}] {peer}");
+    let mut entry = accesslog::Entry {
+        listener: local,
+        peer,
+        query: String::new(),
+        outcome: Outcome::Error,
+        bytes: 0,
+        latency: Duration::ZERO,
+    };
+    let result = serve(&cfg, &mut stream, &from, deadline, &mut entry);
+    entry.latency = start.elapsed();
+    accesslog::log(&entry);
+    result.map_err(|e| Error::new(e.kind(), format!("{from}: {e}")))
+}
+
+/// Reads the query and writes the response, filling in the access log entry
+/// as it goes
+fn serve(
+    cfg: &Config,
+    stream: &mut TcpStream,
+    from: &str,
+    deadline: Instant,
+    entry: &mut accesslog::Entry,
+) -> std::io::Result<()> {
+    let query = match connection::read_query(stream, &cfg.connection, deadline) {
+        Ok(q) => q,
+        Err(e) => {
+            if e.kind() == ErrorKind::InvalidData {
+                entry.outcome = Outcome::TooLong;
+                let msg = b"Query too long\n";
+                if connection::write_response(stream, &cfg.connection, deadline, msg).is_ok() {
+                    entry.bytes = msg.len();
+                }
+            } else if e.kind() == ErrorKind::TimedOut {
+                entry.outcome = Outcome::TimedOut;
+            }
+            return Err(e);
+        }
+    };
+    entry.query = String::from_utf8_lossy(&query).into_owned();
+    let (outcome, response) = if let Ok(query) = String::from_utf8(query) {
+        respond(cfg, from, entry.peer, &query, deadline)?
     } else {
-        let mut path = PathBuf::from("/");
-        path.push(request);
-        path.push(".plan");
-        if path.exists() {
-            let output = fs::read_to_string(path)?;
-            println!("Serving info for user {request}.");
-            _ = stream.write(format!("{output}\n").as_bytes())?;
+        warning!("{from}: Malformed request: query is not valid UTF-8");
+        (Outcome::Malformed, b"Malformed request\n".to_vec())
+    };
+    entry.outcome = outcome;
+    match connection::write_response(stream, &cfg.connection, deadline, &response) {
+        Ok(()) => {
+            entry.bytes = response.len();
+            Ok(())
+        }
+        Err(e) => {
+            if e.kind() == ErrorKind::TimedOut {
+                entry.outcome = Outcome::TimedOut;
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Accepts connections on a single listener, passing them to the pool
+fn accept(listener: &TcpListener, addr: SocketAddr, pool: &Mutex<ThreadPool>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                error!("[{addr}] Unable to accept connection: {e}");
+                thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        let Ok(peer) = stream.peer_addr() else {
+            continue;
+        };
+        let peer = peer.ip().to_canonical();
+        let cfg = config();
+        let guard = match ratelimit::check(&cfg.rate_limit, peer) {
+            Ok(g) => g,
+            Err(e) => {
+                warning!("[{addr}] {peer}: Rejected connection: {e}.");
+                turn_away(&stream, e.reply());
+                continue;
+            }
+        };
+        let Ok(pool) = pool.lock() else {
+            return;
+        };
+        let permit = pool.reserve();
+        if let Some(permit) = permit {
+            permit.execute(move || {
+                if let Err(e) = handle_connection(stream, addr) {
+                    warning!("{e}");
+                }
+                drop(guard);
+            });
         } else {
-            eprintln!("Request for unknown user {request}.");
-            _ = stream.write(format!("{request}'s not here man.\n").as_bytes())?;
+            let total = REJECTED.fetch_add(1, Ordering::Relaxed) + 1;
+            warning!("[{addr}] {peer}: Queue full, rejected connection ({total} total).");
+            if cfg.overload == Overload::Busy {
+                turn_away(&stream, BUSY);
+            }
+        }
+    }
+}
+
+/// Sends a short reply to a connection which is being turned away
+fn turn_away(mut stream: &TcpStream, msg: &[u8]) {
+    // Never let a slow client hold up the accept loop
+    if stream.set_nonblocking(true).is_ok() {
+        _ = stream.write(msg);
+    }
+}
+
+/// Binds the configured addresses, unless sockets were passed in by systemd
+fn listeners(
+    cfg: &Config,
+    activated: Option<Vec<TcpListener>>,
+) -> std::io::Result<Vec<(SocketAddr, TcpListener)>> {
+    let mut listeners = vec![];
+    if let Some(activated) = activated {
+        for listener in activated {
+            let addr = listener.local_addr()?;
+            info!("Received socket for address {addr}.");
+            listeners.push((addr, listener));
+        }
+    } else {
+        for addr in cfg.listen_addrs()? {
+            info!("Binding to address {addr}.");
+            listeners.push((addr, listener::bind(addr)?));
+        }
+    }
+    Ok(listeners)
+}
+
+/// Waits for ctrl-c, logging the cache counts every `cache.log_interval`
+/// seconds in the meantime
+fn wait_for_shutdown() {
+    let (tx, rx) = channel();
+    ctrlc::set_handler(move || {
+        tx.send(()).expect("Cannot send termination signal");
+    })
+    .expect("Cannot set signal handler");
+    loop {
+        // While reports are off, the config is still checked now and then in
+        // case a reload turns them on
+        let interval = config().cache.log_interval;
+        let wait = Duration::from_secs(if interval == 0 { 60 } else { interval });
+        match rx.recv_timeout(wait) {
+            Err(RecvTimeoutError::Timeout) => {
+                if interval != 0 {
+                    cache::report();
+                }
+            }
+            result => {
+                result.expect("Could not receive message through channel");
+                break;
+            }
         }
     }
-    Ok(())
 }
 
-#[allow(clippy::similar_names)]
 fn main() -> std::io::Result<()> {
-    let uid = unsafe { libc::getuid() };
-    let gid = unsafe { libc::getgid() };
-    if uid != 0 && gid != 0 {
-        eprintln!("Toe must be started as the root user.");
-        process::exit(1);
+    reload::block_sighup()?;
+    let cfg = config();
+    // In inetd mode stdout is the connection to the client
+    if ARGS.inetd && cfg.access_log.enabled && cfg.access_log.destination == Destination::Stdout {
+        return Err(Error::other(
+            "the access log cannot be written to stdout in --inetd mode",
+        ));
+    }
+    if ARGS.check {
+        cfg.getpwnam()?;
+        cfg.getgrnam()?;
+        println!("Config file {} is valid.", ARGS.config.display());
+        return Ok(());
+    }
+    if ARGS.print_config {
+        match toml::to_string(&*cfg) {
+            Ok(s) => print!("{s}"),
+            Err(e) => return Err(Error::other(format!("Unable to print config: {e}"))),
+        }
+        return Ok(());
+    }
+    log::open(&cfg.log)?;
+    let activated = if ARGS.inetd {
+        None
     } else {
-        let mut sys = SYS.lock().unwrap();
-        sys.refresh_all();
-        let uptime = Time::uptime(&sys);
-        println!(
+        activation::listeners()?
+    };
+    if !ARGS.privileged {
+        if !ARGS.foreground && !ARGS.inetd && activated.is_none() {
+            error!("Toe must be started as the root user.");
+            process::exit(1);
+        }
+        info!("Not started as root, serving {} without chroot.", cfg.root);
+    } else if !ARGS.inetd {
+        let uptime = Time::uptime(sysinfo::System::new().uptime());
+        info!(
             "Starting toe server at {}:{}...",
             uptime.hours(),
             uptime.minutes()
         );
     }
-    let user = CONFIG.getpwnam()?;
-    let group = CONFIG.getgrnam()?;
-    if CONFIG.chroot {
-        unix::fs::chroot(&CONFIG.root)?;
-    }
-    env::set_current_dir("/")?;
-    let listener = TcpListener::bind(format!("{}:{}", CONFIG.address, CONFIG.port))?;
-    println!(
-        "Binding to address {} on port {}.",
-        CONFIG.address, CONFIG.port
-    );
-    privdrop(user, group)?;
-    if let Ok(mut sys) = SYS.lock() {
-        sys.refresh_all();
-    }
-    println!("Starting up thread pool");
-    let threads = NonZeroUsize::new(CONFIG.threads).unwrap();
-    let pool = Arc::new(Mutex::new(ThreadPool::new(threads)));
-    println!("Priviledges dropped, listening for incoming connections.");
-    {
+    let ids = if ARGS.privileged {
+        Some((cfg.getpwnam()?, cfg.getgrnam()?))
+    } else {
+        None
+    };
+    let config_file = ConfigFile::open(&ARGS.config)?;
+    accesslog::open(&cfg.access_log)?;
+    let inetd = if ARGS.inetd {
+        Some(activation::stdin()?)
+    } else {
+        None
+    };
+    if cfg.chroot {
+        unix::fs::chroot(&cfg.root)?;
+        env::set_current_dir("/")?;
+    }
+    // Plans are started by a helper, which is forked while toe still has a
+    // single thread and its privileges
+    if cfg.users.values().any(|u| u.exec) {
+        exec::start(ids.map(|(user, group)| unsafe { ((*user).pw_uid, (*group).gr_gid) }))?;
+    }
+    if let Some(stream) = inetd {
+        if let Some((user, group)) = ids {
+            privdrop(&cfg, user, group)?;
+        }
+        // Only one query is served, so there is nothing to keep up to date
+        collector::collect_once(&cfg);
+        let local = stream.local_addr()?;
+        return handle_connection(stream, local);
+    }
+    let listeners = listeners(&cfg, activated)?;
+    if let Some((user, group)) = ids {
+        privdrop(&cfg, user, group)?;
+        info!("Privileges dropped.");
+    }
+    collector::start(config);
+    info!("Starting up thread pool");
+    let pool = Arc::new(Mutex::new(ThreadPool::new(cfg.threads, cfg.queue)));
+    info!("Listening for incoming connections.");
+    for (addr, listener) in listeners {
         let pool = Arc::clone(&pool);
-        thread::spawn(move || {
-            for stream in listener.incoming() {
-                let stream = stream.unwrap();
-                if let Ok(pool) = pool.try_lock() {
-                    pool.execute(|| {
-                        if let Err(e) = handle_connection(stream) {
-                            eprintln!("{e}");
-                        }
-                    });
-                }
-            }
-        });
+        thread::spawn(move || accept(&listener, addr, &pool));
     }
-    let (tx, rx) = channel();
-    ctrlc::set_handler(move || {
-        tx.send(()).expect("Cannot send termination signal");
-    })
-    .expect("Cannot set signal handler");
-    rx.recv()
-        .expect("Could not receive message through channel");
-    if let Ok(mut pool) = pool.try_lock() {
+    reload::on_sighup(move || reload(&config_file));
+    wait_for_shutdown();
+    if let Ok(mut pool) = pool.lock() {
         pool.shutdown();
     }
+    cache::report();
     Ok(())
 }
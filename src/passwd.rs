@@ -0,0 +1,49 @@
+//! Reading of user records from a `passwd(5)` file. Toe reads the copy inside
+//! of the server root rather than calling `getpwnam`, so that only the users
+//! which have been placed in the chroot are visible.
+use std::{fs, io::Error, path::Path};
+
+/// A single line of the passwd file
+pub struct Passwd {
+    pub name: String,
+    pub uid: u32,
+    /// The first field of the GECOS field, which holds the user's full name
+    pub full_name: String,
+    pub dir: String,
+    pub shell: String,
+}
+
+impl Passwd {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        let _password = fields.next()?;
+        let uid = fields.next()?.parse().ok()?;
+        let _gid = fields.next()?;
+        let gecos = fields.next()?;
+        let dir = fields.next()?;
+        let shell = fields.next()?;
+        Some(Self {
+            name: String::from(name),
+            uid,
+            full_name: String::from(gecos.split(',').next().unwrap_or_default()),
+            dir: String::from(dir),
+            shell: String::from(shell),
+        })
+    }
+}
+
+/// Reads every well formed entry in the passwd file at `path`
+pub fn read(path: &Path) -> Result<Vec<Passwd>, Error> {
+    let raw = fs::read_to_string(path)?;
+    Ok(raw
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .filter_map(Passwd::parse)
+        .collect())
+}
+
+/// Looks up a single user in the passwd file at `path`
+pub fn lookup(path: &Path, name: &str) -> Result<Option<Passwd>, Error> {
+    Ok(read(path)?.into_iter().find(|p| p.name == name))
+}
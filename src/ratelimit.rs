@@ -0,0 +1,268 @@
+//! Per client rate limiting and caps on concurrent connections
+use {
+    crate::config::{Aggregate, RateLimit},
+    std::{
+        collections::HashMap,
+        fmt,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+        sync::{LazyLock, Mutex},
+        time::{Duration, Instant},
+    },
+};
+
+/// Once this many buckets are being tracked, full buckets are pruned. After
+/// each pruning the threshold is raised to twice the number which remain, so
+/// that the cost of pruning is spread across many connections.
+const PRUNE_AT: usize = 1024;
+
+/// The most clients which are tracked at once. New clients are turned away
+/// while this many are being held back.
+const MAX_BUCKETS: usize = 64 * 1024;
+
+/// How often a full map is pruned
+const FULL_PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+static LIMITER: LazyLock<Limiter> = LazyLock::new(Limiter::default);
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, cfg: &RateLimit, now: Instant) {
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * cfg.rate).min(f64::from(cfg.burst));
+        self.last = now;
+    }
+}
+
+struct Buckets {
+    map: HashMap<IpAddr, Bucket>,
+    /// The size at which the map is next pruned
+    prune_at: usize,
+    pruned: Instant,
+}
+
+impl Default for Buckets {
+    fn default() -> Self {
+        Self {
+            map: HashMap::new(),
+            prune_at: PRUNE_AT,
+            pruned: Instant::now(),
+        }
+    }
+}
+
+impl Buckets {
+    /// Drops the buckets of clients which have been quiet long enough to be
+    /// back to a full allowance, as they are no different to new clients.
+    /// This is only done once the map has grown to `prune_at`, or at most
+    /// once every `FULL_PRUNE_INTERVAL` while it is full.
+    fn prune(&mut self, cfg: &RateLimit, now: Instant) {
+        let full = self.map.len() >= MAX_BUCKETS;
+        if self.map.len() < self.prune_at
+            || (full && now.duration_since(self.pruned) < FULL_PRUNE_INTERVAL)
+        {
+            return;
+        }
+        self.map.retain(|_, b| {
+            b.refill(cfg, now);
+            b.tokens < f64::from(cfg.burst)
+        });
+        self.prune_at = (self.map.len() * 2).clamp(PRUNE_AT, MAX_BUCKETS);
+        self.pruned = now;
+    }
+}
+
+#[derive(Default)]
+struct Limiter {
+    buckets: Mutex<Buckets>,
+    active: Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// Why a connection was turned away
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// The client has used up its allowance of new connections
+    Rate,
+    /// The client already has the maximum number of connections open
+    Concurrency,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rate => write!(f, "rate limit exceeded"),
+            Self::Concurrency => write!(f, "too many concurrent connections"),
+        }
+    }
+}
+
+impl Rejection {
+    /// The line sent to the client before closing the connection
+    pub fn reply(&self) -> &'static [u8] {
+        match self {
+            Self::Rate => b"Too many requests, please slow down.\n",
+            Self::Concurrency => b"Too many connections from your address.\n",
+        }
+    }
+}
+
+/// Counts a connection as open until dropped
+pub struct Guard {
+    key: Option<IpAddr>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            let mut active = LIMITER.active.lock().unwrap();
+            if let Some(count) = active.get_mut(&key) {
+                *count -= 1;
+                if *count == 0 {
+                    active.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+/// The address which limits are tracked under, which is either the client's
+/// own address or the /24 network which it belongs to. IPv6 clients are
+/// always tracked by /64, the smallest network a host is usually given, as
+/// otherwise a single host could pick a fresh address for every connection.
+fn key(cfg: &RateLimit, ip: IpAddr) -> IpAddr {
+    match (cfg.aggregate, ip.to_canonical()) {
+        (Aggregate::Address, IpAddr::V4(ip)) => IpAddr::V4(ip),
+        (Aggregate::Prefix, IpAddr::V4(ip)) => {
+            IpAddr::V4(Ipv4Addr::from(u32::from(ip) & 0xffff_ff00))
+        }
+        (_, IpAddr::V6(ip)) => IpAddr::V6(Ipv6Addr::from(
+            u128::from(ip) & 0xffff_ffff_ffff_ffff_0000_0000_0000_0000,
+        )),
+    }
+}
+
+/// Checks a new connection from `ip` against the limits, returning a guard
+/// which must be held until the connection is closed
+pub fn check(cfg: &RateLimit, ip: IpAddr) -> Result<Guard, Rejection> {
+    let key = key(cfg, ip);
+    if cfg.enabled {
+        let now = Instant::now();
+        let mut buckets = LIMITER.buckets.lock().unwrap();
+        buckets.prune(cfg, now);
+        if buckets.map.len() >= MAX_BUCKETS && !buckets.map.contains_key(&key) {
+            return Err(Rejection::Rate);
+        }
+        let bucket = buckets.map.entry(key).or_insert_with(|| Bucket {
+            tokens: f64::from(cfg.burst),
+            last: now,
+        });
+        bucket.refill(cfg, now);
+        if bucket.tokens < 1.0 {
+            return Err(Rejection::Rate);
+        }
+        bucket.tokens -= 1.0;
+    }
+    if cfg.max_concurrent == 0 {
+        return Ok(Guard { key: None });
+    }
+    let mut active = LIMITER.active.lock().unwrap();
+    let count = active.entry(key).or_insert(0);
+    if *count >= cfg.max_concurrent {
+        return Err(Rejection::Concurrency);
+    }
+    *count += 1;
+    Ok(Guard { key: Some(key) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(rate: f64, burst: u32) -> RateLimit {
+        RateLimit {
+            enabled: true,
+            rate,
+            burst,
+            ..RateLimit::default()
+        }
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn refill() {
+        let cfg = limits(2.0, 5);
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 0.0,
+            last: start,
+        };
+        bucket.refill(&cfg, start + Duration::from_millis(1500));
+        assert!((bucket.tokens - 3.0).abs() < 1e-9);
+        // Never more than the burst, however long the client was quiet
+        bucket.refill(&cfg, start + Duration::from_secs(60));
+        assert!((bucket.tokens - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn burst_then_rate() {
+        // Slow enough that no token comes back while the test runs
+        let cfg = limits(0.001, 3);
+        for _ in 0..3 {
+            assert!(check(&cfg, ip("192.0.2.1")).is_ok());
+        }
+        assert_eq!(check(&cfg, ip("192.0.2.1")).err(), Some(Rejection::Rate));
+        // Other clients have their own allowance
+        assert!(check(&cfg, ip("192.0.2.2")).is_ok());
+    }
+
+    #[test]
+    fn concurrency() {
+        let cfg = RateLimit {
+            max_concurrent: 2,
+            ..RateLimit::default()
+        };
+        let first = check(&cfg, ip("198.51.100.1")).unwrap();
+        let _second = check(&cfg, ip("198.51.100.1")).unwrap();
+        assert_eq!(
+            check(&cfg, ip("198.51.100.1")).err(),
+            Some(Rejection::Concurrency)
+        );
+        drop(first);
+        assert!(check(&cfg, ip("198.51.100.1")).is_ok());
+    }
+
+    #[test]
+    fn keys() {
+        let mut cfg = RateLimit::default();
+        assert_eq!(key(&cfg, ip("203.0.113.9")), ip("203.0.113.9"));
+        assert_eq!(key(&cfg, ip("::ffff:203.0.113.9")), ip("203.0.113.9"));
+        assert_eq!(key(&cfg, ip("2001:db8:1:2:3:4:5:6")), ip("2001:db8:1:2::"));
+        cfg.aggregate = Aggregate::Prefix;
+        assert_eq!(key(&cfg, ip("203.0.113.9")), ip("203.0.113.0"));
+        assert_eq!(key(&cfg, ip("2001:db8:1:2:3:4:5:6")), ip("2001:db8:1:2::"));
+    }
+
+    #[test]
+    fn prune() {
+        let cfg = limits(1.0, 2);
+        let now = Instant::now();
+        let mut buckets = Buckets::default();
+        for i in 0..PRUNE_AT {
+            let tokens = if i % 2 == 0 { 0.0 } else { 2.0 };
+            let ip = IpAddr::V4(Ipv4Addr::from(u32::try_from(i).unwrap()));
+            buckets.map.insert(ip, Bucket { tokens, last: now });
+        }
+        // Only full buckets go, and the next pruning waits until the map has
+        // doubled
+        buckets.prune(&cfg, now);
+        assert_eq!(buckets.map.len(), PRUNE_AT / 2);
+        assert_eq!(buckets.prune_at, PRUNE_AT);
+        assert!(buckets.map.values().all(|b| b.tokens < 1.0));
+    }
+}
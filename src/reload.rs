@@ -0,0 +1,121 @@
+//! Reloading of the config file on `SIGHUP`
+use std::{
+    ffi::{CStr, CString},
+    fs::File,
+    io::{Error, Read},
+    mem,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, FromRawFd, OwnedFd},
+    },
+    path::{Path, PathBuf},
+    ptr, thread,
+};
+
+/// A handle to the config file which remains usable after entering the chroot.
+/// Rather than holding the file itself open, which would miss edits made by
+/// replacing the file, we hold its parent directory open and re-open the file
+/// relative to it on each reload.
+pub struct ConfigFile {
+    dir: OwnedFd,
+    name: CString,
+    path: PathBuf,
+}
+
+impl ConfigFile {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let dir = match path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        let Some(name) = path.file_name() else {
+            return Err(Error::other(format!("{} is not a file", path.display())));
+        };
+        let name = CString::new(name.as_bytes())?;
+        let dirname = CString::new(dir.as_os_str().as_bytes())?;
+        let fd = unsafe {
+            libc::open(
+                dirname.as_ptr(),
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(Self {
+            dir: unsafe { OwnedFd::from_raw_fd(fd) },
+            name,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// The path to the config file as it was given at startup
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the current contents of the config file
+    pub fn read(&self) -> Result<String, Error> {
+        self.read_at(&self.name)
+    }
+
+    /// Reads a file given relative to the directory holding the config file
+    pub fn read_relative(&self, path: &Path) -> Result<String, Error> {
+        self.read_at(&CString::new(path.as_os_str().as_bytes())?)
+    }
+
+    fn read_at(&self, name: &CStr) -> Result<String, Error> {
+        let fd = unsafe {
+            libc::openat(
+                self.dir.as_raw_fd(),
+                name.as_ptr(),
+                libc::O_RDONLY | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        let mut raw = String::new();
+        file.read_to_string(&mut raw)?;
+        Ok(raw)
+    }
+}
+
+fn sighup_set() -> libc::sigset_t {
+    unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&raw mut set);
+        libc::sigaddset(&raw mut set, libc::SIGHUP);
+        set
+    }
+}
+
+/// Blocks `SIGHUP` for the calling thread and any threads it later spawns, so
+/// that the signal is only ever received by the thread started in `on_sighup`.
+/// This must be called before any other threads are started.
+pub fn block_sighup() -> Result<(), Error> {
+    let set = sighup_set();
+    let ret = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &raw const set, ptr::null_mut()) };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(Error::from_raw_os_error(ret))
+    }
+}
+
+/// Spawns a thread which runs `f` each time that a `SIGHUP` is received
+pub fn on_sighup<F>(f: F)
+where
+    F: Fn() + Send + 'static,
+{
+    thread::spawn(move || {
+        let set = sighup_set();
+        loop {
+            let mut sig = 0;
+            if unsafe { libc::sigwait(&raw const set, &raw mut sig) } == 0 && sig == libc::SIGHUP {
+                f();
+            }
+        }
+    });
+}
@@ -0,0 +1,208 @@
+//! Parsing of finger queries as described in
+//! [RFC 1288 section 2.3](https://datatracker.ietf.org/doc/html/rfc1288#section-2.3)
+//!
+//! ```text
+//! {Q1}    ::= [{W}|{W}{S}{U}]{C}
+//! {Q2}    ::= [{W}{S}][{U}]{H}{C}
+//! {U}     ::= username
+//! {H}     ::= @hostname | @hostname{H}
+//! {W}     ::= /W
+//! {S}     ::= <SP> | <SP>{S}
+//! {C}     ::= <CRLF>
+//! ```
+use std::{fmt, str::FromStr};
+
+/// A parsed finger query
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// The empty query, which lists information about the server and its users
+    List { verbose: bool },
+    /// A query for a single user on this host
+    User { name: String, verbose: bool },
+    /// A query which is to be forwarded on to another host
+    Forward {
+        user: Option<String>,
+        hosts: Vec<String>,
+        verbose: bool,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// More than one username or token was given
+    TooManyTokens,
+    /// A hostname in the `{H}` portion of the query was empty
+    EmptyHost,
+    /// The query contained control characters
+    InvalidCharacter,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooManyTokens => write!(f, "too many tokens in query"),
+            Self::EmptyHost => write!(f, "empty hostname in query"),
+            Self::InvalidCharacter => write!(f, "invalid character in query"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl FromStr for Request {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim_end_matches(['\r', '\n']);
+        if s.chars().any(char::is_control) {
+            return Err(ParseError::InvalidCharacter);
+        }
+        let mut tokens = s.split(' ').filter(|x| !x.is_empty());
+        let mut token = tokens.next();
+        let verbose = matches!(token, Some(t) if t.eq_ignore_ascii_case("/W"));
+        if verbose {
+            token = tokens.next();
+        }
+        if tokens.next().is_some() {
+            return Err(ParseError::TooManyTokens);
+        }
+        let Some(token) = token else {
+            return Ok(Self::List { verbose });
+        };
+        let mut parts = token.split('@');
+        let user = parts.next().filter(|x| !x.is_empty()).map(String::from);
+        let hosts = parts
+            .map(|h| {
+                if h.is_empty() {
+                    Err(ParseError::EmptyHost)
+                } else {
+                    Ok(String::from(h))
+                }
+            })
+            .collect::<Result<Vec<String>, ParseError>>()?;
+        if hosts.is_empty() {
+            // `user` is always `Some` here, as an empty token would have been
+            // filtered out above
+            Ok(Self::User {
+                name: user.unwrap_or_default(),
+                verbose,
+            })
+        } else {
+            Ok(Self::Forward {
+                user,
+                hosts,
+                verbose,
+            })
+        }
+    }
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verbose = match self {
+            Self::List { verbose } | Self::User { verbose, .. } | Self::Forward { verbose, .. } => {
+                *verbose
+            }
+        };
+        if verbose {
+            write!(f, "/W")?;
+        }
+        match self {
+            Self::List { .. } => Ok(()),
+            Self::User { name, .. } => {
+                if verbose {
+                    write!(f, " ")?;
+                }
+                write!(f, "{name}")
+            }
+            Self::Forward { user, hosts, .. } => {
+                if verbose {
+                    write!(f, " ")?;
+                }
+                if let Some(user) = user {
+                    write!(f, "{user}")?;
+                }
+                hosts.iter().try_for_each(|h| write!(f, "@{h}"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list() {
+        assert_eq!("\r\n".parse(), Ok(Request::List { verbose: false }));
+        assert_eq!("/W\r\n".parse(), Ok(Request::List { verbose: true }));
+        assert_eq!("/w".parse(), Ok(Request::List { verbose: true }));
+    }
+
+    #[test]
+    fn user() {
+        assert_eq!(
+            "jack\r\n".parse(),
+            Ok(Request::User {
+                name: String::from("jack"),
+                verbose: false
+            })
+        );
+        assert_eq!(
+            "/W   jack".parse(),
+            Ok(Request::User {
+                name: String::from("jack"),
+                verbose: true
+            })
+        );
+    }
+
+    #[test]
+    fn forward() {
+        assert_eq!(
+            "jack@a@b".parse(),
+            Ok(Request::Forward {
+                user: Some(String::from("jack")),
+                hosts: vec![String::from("a"), String::from("b")],
+                verbose: false
+            })
+        );
+        assert_eq!(
+            "/W @a".parse(),
+            Ok(Request::Forward {
+                user: None,
+                hosts: vec![String::from("a")],
+                verbose: true
+            })
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            "jack jill".parse::<Request>(),
+            Err(ParseError::TooManyTokens)
+        );
+        assert_eq!(
+            "/W jack jill".parse::<Request>(),
+            Err(ParseError::TooManyTokens)
+        );
+        assert_eq!("jack@".parse::<Request>(), Err(ParseError::EmptyHost));
+        assert_eq!("jack@@a".parse::<Request>(), Err(ParseError::EmptyHost));
+        assert_eq!(
+            "ja\x1bck".parse::<Request>(),
+            Err(ParseError::InvalidCharacter)
+        );
+        assert_eq!(
+            "jack\tjill".parse::<Request>(),
+            Err(ParseError::InvalidCharacter)
+        );
+    }
+
+    #[test]
+    fn display() {
+        for query in ["", "/W", "jack", "/W jack", "jack@a@b", "/W @a"] {
+            assert_eq!(query.parse::<Request>().unwrap().to_string(), query);
+        }
+    }
+}
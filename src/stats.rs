@@ -0,0 +1,428 @@
+//! The system information shown in response to the empty query
+use {
+    crate::{
+        collector::{self, Sensor},
+        config::{Config, Stats, Units},
+        exec, passwd,
+        time::Time,
+        username::Username,
+        utmp,
+    },
+    chrono::Timelike,
+    std::{fmt::Write as _, fs},
+};
+
+/// Appends a single stat to `sysinfo`
+pub fn render(cfg: &Config, stat: Stats, sysinfo: String) -> Result<String, std::fmt::Error> {
+    match stat {
+        Stats::Users => user_info(cfg, sysinfo),
+        Stats::Listing => listing_info(cfg, sysinfo),
+        Stats::Uptime => uptime_info(sysinfo),
+        Stats::Kernel => kernel_info(sysinfo),
+        Stats::Cpu => cpu_info(cfg, sysinfo),
+        Stats::Sensors => sensor_info(cfg, sysinfo),
+        Stats::Memory => memory_info(sysinfo),
+        Stats::Swap => swap_info(sysinfo),
+        Stats::Disks => disk_info(cfg, sysinfo),
+        Stats::Network => network_info(cfg, sysinfo),
+    }
+}
+
+/// The users with a directory in the server root, sorted by name
+pub fn users(cfg: &Config) -> Vec<Username> {
+    let mut users = vec![];
+    if let Ok(dir) = fs::read_dir(cfg.server_root()) {
+        for entry in dir.flatten() {
+            if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                if let Ok(name) = name.parse::<Username>() {
+                    users.push(name);
+                }
+            }
+        }
+    }
+    users.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+    users
+}
+
+/// Whether a user shares any of the files shown in the short form
+pub fn shares_files(cfg: &Config, name: &Username) -> bool {
+    let root = cfg.server_root();
+    cfg.user_files(name.as_ref(), false).any(|file| {
+        matches!(name.open(&root, &file.name, cfg.symlinks), Ok(Some(_)))
+            || (file.name == ".plan"
+                && exec::programs(cfg, name, &root).is_ok_and(|p| !p.is_empty()))
+    })
+}
+
+fn kernel_info(mut sysinfo: String) -> Result<String, std::fmt::Error> {
+    let snapshot = collector::snapshot();
+    if let Some(name) = &snapshot.os_name {
+        write!(sysinfo, "{name} ")?;
+    }
+    if let Some(kern) = &snapshot.kernel_version {
+        write!(sysinfo, "{kern} ")?;
+    }
+    if let Some(os) = &snapshot.os_version {
+        write!(sysinfo, "{os} ")?;
+    }
+    write!(sysinfo, "\n\n")?;
+    Ok(sysinfo)
+}
+
+fn user_info(cfg: &Config, mut sysinfo: String) -> Result<String, std::fmt::Error> {
+    write!(sysinfo, "Users: ")?;
+    for name in users(cfg) {
+        if shares_files(cfg, &name) {
+            write!(sysinfo, " {name}")?;
+        }
+    }
+    write!(sysinfo, "\n\n")?;
+    Ok(sysinfo)
+}
+
+/// Formats the time since a terminal was last used
+fn idle(seconds: u64) -> String {
+    let minutes = seconds / 60;
+    if minutes == 0 {
+        String::new()
+    } else if minutes < 60 {
+        minutes.to_string()
+    } else if minutes < 24 * 60 {
+        format!("{}:{:02}", minutes / 60, minutes % 60)
+    } else {
+        format!("{}d", minutes / (24 * 60))
+    }
+}
+
+/// Lists each login session in a table, in the style of the classic fingerd
+fn listing_info(cfg: &Config, mut sysinfo: String) -> Result<String, std::fmt::Error> {
+    let mut sessions = if cfg.header.utmp.is_empty() {
+        vec![]
+    } else {
+        utmp::sessions(&cfg.root_path(&cfg.header.utmp)).unwrap_or_default()
+    };
+    if sessions.is_empty() {
+        write!(sysinfo, "No one logged on.\n\n")?;
+        return Ok(sysinfo);
+    }
+    sessions.sort_by(|a, b| (&a.user, &a.line).cmp(&(&b.user, &b.line)));
+    let passwd = passwd::read(&cfg.root_path(&cfg.header.passwd)).unwrap_or_default();
+    let now = std::time::SystemTime::now();
+    write!(sysinfo, "{:<12}{:<24}", "Login", "Name")?;
+    if cfg.listing.tty {
+        write!(sysinfo, "{:<10}", "Tty")?;
+    }
+    if cfg.listing.idle {
+        write!(sysinfo, "{:>6}  ", "Idle")?;
+    }
+    writeln!(sysinfo, "Plan")?;
+    for session in &sessions {
+        let full_name = passwd
+            .iter()
+            .find(|p| p.name == session.user)
+            .filter(|_| cfg.header.name)
+            .map(|p| p.full_name.chars().take(23).collect::<String>())
+            .unwrap_or_default();
+        let plan = session
+            .user
+            .parse::<Username>()
+            .is_ok_and(|name| shares_files(cfg, &name));
+        write!(sysinfo, "{:<12}{full_name:<24}", session.user)?;
+        if cfg.listing.tty {
+            write!(sysinfo, "{:<10}", session.line)?;
+        }
+        if cfg.listing.idle {
+            let idle = fs::metadata(cfg.root_path("dev").join(&session.line))
+                .and_then(|m| m.accessed())
+                .ok()
+                .and_then(|a| now.duration_since(a).ok())
+                .map(|d| idle(d.as_secs()))
+                .unwrap_or_default();
+            write!(sysinfo, "{idle:>6}  ")?;
+        }
+        writeln!(sysinfo, "{}", if plan { "yes" } else { "no" })?;
+    }
+    writeln!(sysinfo)?;
+    Ok(sysinfo)
+}
+
+fn uptime_info(mut sysinfo: String) -> Result<String, std::fmt::Error> {
+    let snapshot = collector::snapshot();
+    write!(sysinfo, "System Status\n-------------\n\n")?;
+    let current = chrono::Utc::now();
+    let uptime = Time::uptime(snapshot.uptime());
+    let users = snapshot.users;
+    let (one, five, fifteen) = snapshot.load;
+    write!(
+        sysinfo,
+        "{:02}:{:02}:{:02} up {} days {:02}:{:02}, {users} users, load average {} {} {}\n\n",
+        current.hour(),
+        current.minute(),
+        current.second(),
+        uptime.days(),
+        uptime.hours(),
+        uptime.minutes(),
+        one,
+        five,
+        fifteen,
+    )?;
+    Ok(sysinfo)
+}
+
+/// Matches `text` against a pattern in which `*` matches any run of
+/// characters and `?` matches any single character
+fn glob(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the most recent `*` if the rest fails to match
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => {
+                let Some((sp, st)) = star else {
+                    return false;
+                };
+                p = sp + 1;
+                t = st + 1;
+                star = Some((sp, st + 1));
+            }
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Collects the readings whose labels match `include` but not `exclude`,
+/// renamed by the first matching rule. Readings which end up with the same
+/// label as an earlier one are dropped.
+fn readings<I: AsRef<str>>(
+    sensors: &[Sensor],
+    include: &[I],
+    exclude: &[I],
+    rename: &[(I, I)],
+) -> Vec<Sensor> {
+    let mut readings: Vec<Sensor> = vec![];
+    for sensor in sensors {
+        let label = sensor.label.as_str();
+        if !include.is_empty() && !include.iter().any(|p| glob(p.as_ref(), label)) {
+            continue;
+        }
+        if exclude.iter().any(|p| glob(p.as_ref(), label)) {
+            continue;
+        }
+        let label = rename
+            .iter()
+            .find_map(|(from, to)| {
+                label
+                    .strip_prefix(from.as_ref())
+                    .map(|rest| format!("{}{rest}", to.as_ref()))
+            })
+            .unwrap_or_else(|| String::from(label));
+        if readings.iter().any(|r| r.label == label) {
+            continue;
+        }
+        readings.push(Sensor {
+            label,
+            ..sensor.clone()
+        });
+    }
+    readings
+}
+
+/// Formats a temperature given in degrees Celsius
+fn temperature(celsius: f32, units: Units) -> String {
+    match units {
+        Units::Celsius => format!("{celsius:+.1}°C"),
+        Units::Fahrenheit => format!("{:+.1}°F", celsius * 9.0 / 5.0 + 32.0),
+    }
+}
+
+fn write_readings(
+    mut sysinfo: String,
+    readings: &[Sensor],
+    units: Units,
+) -> Result<String, std::fmt::Error> {
+    let width = readings
+        .iter()
+        .map(|r| r.label.len() + 1)
+        .max()
+        .unwrap_or(0);
+    for reading in readings {
+        let label = format!("{}:", reading.label);
+        write!(
+            sysinfo,
+            "{label:<width$} {}",
+            temperature(reading.temperature, units)
+        )?;
+        let limits = [("max", reading.max), ("critical", reading.critical)]
+            .into_iter()
+            .filter_map(|(name, value)| Some(format!("{name} = {}", temperature(value?, units))))
+            .collect::<Vec<_>>();
+        if !limits.is_empty() {
+            write!(sysinfo, "  ({})", limits.join(", "))?;
+        }
+        writeln!(sysinfo)?;
+    }
+    Ok(sysinfo)
+}
+
+/// The processor temperatures from the common Intel, AMD and Raspberry Pi
+/// drivers
+fn cpu_info(cfg: &Config, sysinfo: String) -> Result<String, std::fmt::Error> {
+    const INCLUDE: [&str; 5] = [
+        "coretemp Core*",
+        "cpu_thermal temp*",
+        "k10temp*",
+        "Core*",
+        "CPU*",
+    ];
+    const RENAME: [(&str, &str); 2] = [("coretemp ", ""), ("cpu_thermal temp", "Core ")];
+    let snapshot = collector::snapshot();
+    let readings = readings(&snapshot.sensors, &INCLUDE, &[], &RENAME);
+    write_readings(sysinfo, &readings, cfg.sensors.units)
+}
+
+fn sensor_info(cfg: &Config, mut sysinfo: String) -> Result<String, std::fmt::Error> {
+    let snapshot = collector::snapshot();
+    write!(sysinfo, "Sensors\n-------\n\n")?;
+    let sensors = &cfg.sensors;
+    let rename = sensors
+        .rename
+        .iter()
+        .map(|r| (r.from.as_str(), r.to.as_str()))
+        .collect::<Vec<_>>();
+    let include = sensors
+        .include
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let exclude = sensors
+        .exclude
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let readings = readings(&snapshot.sensors, &include, &exclude, &rename);
+    sysinfo = write_readings(sysinfo, &readings, sensors.units)?;
+    writeln!(sysinfo)?;
+    Ok(sysinfo)
+}
+
+/// Formats a number of bytes using binary units
+fn bytes(n: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    if n < 1024 {
+        return format!("{n} B");
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let mut value = n as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// The share of `total` which is `used`, as a whole percentage
+fn percent(used: u64, total: u64) -> u64 {
+    used.saturating_mul(100).checked_div(total).unwrap_or(0)
+}
+
+fn memory_info(mut sysinfo: String) -> Result<String, std::fmt::Error> {
+    let memory = &collector::snapshot().memory;
+    write!(sysinfo, "Memory\n------\n\n")?;
+    let total = memory.total;
+    let used = memory.used;
+    write!(
+        sysinfo,
+        "Total {}, used {} ({}%), available {}\n\n",
+        bytes(total),
+        bytes(used),
+        percent(used, total),
+        bytes(memory.available),
+    )?;
+    Ok(sysinfo)
+}
+
+fn swap_info(mut sysinfo: String) -> Result<String, std::fmt::Error> {
+    let memory = &collector::snapshot().memory;
+    write!(sysinfo, "Swap\n----\n\n")?;
+    let total = memory.swap_total;
+    let used = memory.swap_used;
+    write!(
+        sysinfo,
+        "Total {}, used {} ({}%), free {}\n\n",
+        bytes(total),
+        bytes(used),
+        percent(used, total),
+        bytes(memory.swap_free),
+    )?;
+    Ok(sysinfo)
+}
+
+fn disk_info(cfg: &Config, mut sysinfo: String) -> Result<String, std::fmt::Error> {
+    let snapshot = collector::snapshot();
+    write!(sysinfo, "Disks\n-----\n\n")?;
+    for disk in &snapshot.disks {
+        let mount = &disk.mount;
+        if !cfg.disks.mounts.is_empty() && !cfg.disks.mounts.contains(mount) {
+            continue;
+        }
+        let total = disk.total;
+        let available = disk.available;
+        let used = total.saturating_sub(available);
+        writeln!(
+            sysinfo,
+            "{mount:<16} {:<8} total {}, used {} ({}%), available {}",
+            disk.file_system,
+            bytes(total),
+            bytes(used),
+            percent(used, total),
+            bytes(available),
+        )?;
+    }
+    writeln!(sysinfo)?;
+    Ok(sysinfo)
+}
+
+/// Formats the rate at which a counter is growing
+fn rate(rate: Option<u64>) -> String {
+    rate.map_or_else(|| String::from("-"), |r| format!("{}/s", bytes(r)))
+}
+
+fn network_info(cfg: &Config, mut sysinfo: String) -> Result<String, std::fmt::Error> {
+    let snapshot = collector::snapshot();
+    write!(sysinfo, "Network\n-------\n\n")?;
+    for interface in &snapshot.interfaces {
+        let name = &interface.name;
+        let shown = if cfg.network.interfaces.is_empty() {
+            name != "lo"
+        } else {
+            cfg.network.interfaces.contains(name)
+        };
+        if !shown {
+            continue;
+        }
+        writeln!(
+            sysinfo,
+            "{name}: rx {} ({}), tx {} ({})",
+            bytes(interface.received),
+            rate(interface.rx_rate),
+            bytes(interface.transmitted),
+            rate(interface.tx_rate),
+        )?;
+    }
+    writeln!(sysinfo)?;
+    Ok(sysinfo)
+}
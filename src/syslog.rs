@@ -0,0 +1,42 @@
+//! A minimal client for the local syslog daemon. The socket is connected at
+//! startup, as `/dev/log` is not reachable once inside of the chroot.
+use std::{io::Error, os::unix::net::UnixDatagram, process};
+
+/// The path of the local syslog socket
+const SOCKET: &str = "/dev/log";
+
+/// The `daemon` facility, used for all of toe's messages
+const FACILITY: u8 = 3;
+
+/// Message severities, as defined in RFC 5424
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error = 3,
+    Warning = 4,
+    Info = 6,
+    Debug = 7,
+}
+
+pub struct Syslog {
+    socket: UnixDatagram,
+    pid: u32,
+}
+
+impl Syslog {
+    pub fn connect() -> Result<Self, Error> {
+        let socket = UnixDatagram::unbound()?;
+        socket
+            .connect(SOCKET)
+            .map_err(|e| Error::new(e.kind(), format!("{SOCKET}: {e}")))?;
+        Ok(Self {
+            socket,
+            pid: process::id(),
+        })
+    }
+
+    pub fn send(&self, severity: Severity, msg: &str) -> Result<(), Error> {
+        let pri = FACILITY * 8 + severity as u8;
+        let line = format!("<{pri}>toe[{}]: {msg}", self.pid);
+        self.socket.send(line.as_bytes()).map(|_| ())
+    }
+}
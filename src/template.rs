@@ -0,0 +1,280 @@
+//! Templates for the server info page. A template is plain text with
+//! placeholders in braces, which are replaced each time it is served:
+//!
+//! ```text
+//! {server}          the server name
+//! {underline}       a row of `=` as long as the server name
+//! {date}            the current date, or {date:FORMAT} with a strftime format
+//! {time}            the current time, or {time:FORMAT}
+//! {kernel}          any of the stats, named as in the `stats` list but in
+//!                   lower case
+//! {include:PATH}    the contents of a file relative to the directory holding
+//!                   the config file, read along with the template
+//! {{ and }}         literal braces
+//! ```
+use {
+    crate::{
+        config::{Config, Stats},
+        stats,
+    },
+    chrono::format::{Item, StrftimeItems},
+    std::{
+        fmt::{self, Write as _},
+        io::Error,
+        path::Path,
+        str::FromStr,
+    },
+};
+
+const DATE: &str = "%a %b %e %Y";
+const TIME: &str = "%H:%M:%S";
+
+#[derive(Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Server,
+    Underline,
+    /// The current date or time in the given format
+    Now(String),
+    Stat(Stats),
+    /// A file's path, and its contents once they have been read
+    Include(String, String),
+}
+
+/// A parsed template
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Template(Vec<Part>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    /// A `{` with no matching `}`
+    Unclosed(usize),
+    /// A `}` which does not close a placeholder
+    Unopened(usize),
+    Unknown(usize, String),
+    /// A date or time format which chrono cannot use
+    Format(usize, String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unclosed(line) => write!(f, "line {line}: unclosed placeholder"),
+            Self::Unopened(line) => write!(f, "line {line}: unmatched `}}`, use `}}}}`"),
+            Self::Unknown(line, name) => write!(f, "line {line}: unknown placeholder `{{{name}}}`"),
+            Self::Format(line, format) => write!(f, "line {line}: invalid format `{format}`"),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+fn stat(name: &str) -> Option<Stats> {
+    match name {
+        "users" => Some(Stats::Users),
+        "listing" => Some(Stats::Listing),
+        "uptime" => Some(Stats::Uptime),
+        "kernel" => Some(Stats::Kernel),
+        "cpu" => Some(Stats::Cpu),
+        "memory" => Some(Stats::Memory),
+        "swap" => Some(Stats::Swap),
+        "disks" => Some(Stats::Disks),
+        "network" => Some(Stats::Network),
+        "sensors" => Some(Stats::Sensors),
+        _ => None,
+    }
+}
+
+fn placeholder(line: usize, name: &str) -> Result<Part, TemplateError> {
+    let (key, arg) = match name.split_once(':') {
+        Some((key, arg)) => (key, Some(arg)),
+        None => (name, None),
+    };
+    match (key, arg) {
+        ("server", None) => Ok(Part::Server),
+        ("underline", None) => Ok(Part::Underline),
+        ("date" | "time", arg) => {
+            let format = arg.unwrap_or(if key == "date" { DATE } else { TIME });
+            if StrftimeItems::new(format).any(|i| matches!(i, Item::Error)) {
+                Err(TemplateError::Format(line, String::from(format)))
+            } else {
+                Ok(Part::Now(String::from(format)))
+            }
+        }
+        ("include", Some(path)) if !path.is_empty() => {
+            Ok(Part::Include(String::from(path), String::new()))
+        }
+        (key, None) => stat(key)
+            .map(Part::Stat)
+            .ok_or_else(|| TemplateError::Unknown(line, String::from(name))),
+        _ => Err(TemplateError::Unknown(line, String::from(name))),
+    }
+}
+
+impl FromStr for Template {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut line = 1;
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '}' => return Err(TemplateError::Unopened(line)),
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some('\n') | None => return Err(TemplateError::Unclosed(line)),
+                            Some(c) => name.push(c),
+                        }
+                    }
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(placeholder(line, name.trim())?);
+                }
+                c => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    text.push(c);
+                }
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self(parts))
+    }
+}
+
+impl Template {
+    /// The stats which appear in the template
+    pub fn stats(&self) -> impl Iterator<Item = Stats> + '_ {
+        self.0.iter().filter_map(|part| match part {
+            Part::Stat(stat) => Some(*stat),
+            _ => None,
+        })
+    }
+
+    /// Reads the files which are included, so that they are not read again
+    /// each time the template is served
+    pub fn read_includes(
+        &mut self,
+        read: impl Fn(&Path) -> Result<String, Error>,
+    ) -> Result<(), Error> {
+        for part in &mut self.0 {
+            if let Part::Include(path, contents) = part {
+                *contents = read(Path::new(path))
+                    .map_err(|e| Error::new(e.kind(), format!("include {path}: {e}")))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills in the placeholders
+    pub fn render(&self, cfg: &Config) -> Result<String, fmt::Error> {
+        let now = chrono::Local::now();
+        let mut out = String::new();
+        for part in &self.0 {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Server => out.push_str(&cfg.server),
+                Part::Underline => {
+                    for _ in 0..cfg.server.len() {
+                        out.push('=');
+                    }
+                }
+                Part::Now(format) => write!(out, "{}", now.format(format))?,
+                Part::Stat(stat) => out = stats::render(cfg, *stat, out)?,
+                Part::Include(_, contents) => out.push_str(contents),
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<Template, TemplateError> {
+        s.parse()
+    }
+
+    #[test]
+    fn parts() {
+        let template = parse("{server}\n{underline}\n{{{uptime}}} {date:%Y}").unwrap();
+        assert!(
+            template.0
+                == [
+                    Part::Server,
+                    Part::Text("\n".into()),
+                    Part::Underline,
+                    Part::Text("\n{".into()),
+                    Part::Stat(Stats::Uptime),
+                    Part::Text("} ".into()),
+                    Part::Now("%Y".into()),
+                ]
+        );
+        assert!(template.stats().eq([Stats::Uptime]));
+        assert!(parse("{ time }").unwrap().0 == [Part::Now(TIME.into())]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse("one\n{server").err(),
+            Some(TemplateError::Unclosed(2))
+        );
+        assert_eq!(parse("{server\n}").err(), Some(TemplateError::Unclosed(1)));
+        assert_eq!(parse("a\nb\nc}").err(), Some(TemplateError::Unopened(3)));
+        assert_eq!(
+            parse("\n{servers}").err(),
+            Some(TemplateError::Unknown(2, "servers".into()))
+        );
+        assert_eq!(
+            parse("{server:x}").err(),
+            Some(TemplateError::Unknown(1, "server:x".into()))
+        );
+        assert_eq!(
+            parse("{include:}").err(),
+            Some(TemplateError::Unknown(1, "include:".into()))
+        );
+        assert_eq!(
+            parse("{date:%Q}").err(),
+            Some(TemplateError::Format(1, "%Q".into()))
+        );
+    }
+
+    #[test]
+    fn render() {
+        let mut template = parse("{server}\n{underline}\n{include:motd}{{}}\n").unwrap();
+        template
+            .read_includes(|path| {
+                assert_eq!(path, Path::new("motd"));
+                Ok(String::from("Welcome\n"))
+            })
+            .unwrap();
+        let cfg = Config {
+            server: String::from("host"),
+            ..Config::default()
+        };
+        assert_eq!(template.render(&cfg).unwrap(), "host\n====\nWelcome\n{}\n");
+        let mut missing = parse("{include:gone}").unwrap();
+        assert!(missing
+            .read_includes(|_| Err(Error::from(std::io::ErrorKind::NotFound)))
+            .is_err());
+    }
+}
@@ -1,12 +1,22 @@
-use std::{
-    num::NonZeroUsize,
-    sync::{mpsc, Arc, Mutex},
-    thread,
+use {
+    crate::log::debug,
+    std::{
+        num::NonZeroUsize,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc, Mutex,
+        },
+        thread,
+    },
 };
 
 pub struct ThreadPool {
     workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>,
+    sender: mpsc::SyncSender<Message>,
+    /// The number of jobs which have been queued but not yet picked up by a worker
+    queued: Arc<AtomicUsize>,
+    /// The maximum number of jobs which may be waiting in the queue
+    depth: usize,
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
@@ -16,49 +26,58 @@ enum Message {
     Terminate,
 }
 
+/// A reserved place in the job queue, obtained from `ThreadPool::reserve`.
+/// The place is released if the permit is dropped without being used.
+pub struct Permit<'a> {
+    pool: &'a ThreadPool,
+}
+
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        println!("Sending terminate message to all workers");
-        for _ in &self.workers {
-            self.sender.send(Message::Terminate).unwrap();
-        }
-        println!("Shutting down all workers");
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
-            }
-        }
+        self.shutdown();
     }
 }
 
 impl ThreadPool {
-    pub fn new(size: NonZeroUsize) -> Self {
-        let (sender, receiver) = mpsc::channel();
+    pub fn new(size: NonZeroUsize, depth: NonZeroUsize) -> Self {
+        let depth = usize::from(depth);
+        let (sender, receiver) = mpsc::sync_channel(depth);
         let receiver = Arc::new(Mutex::new(receiver));
+        let queued = Arc::new(AtomicUsize::new(0));
         let mut workers = Vec::with_capacity(usize::from(size));
         for id in 0..usize::from(size) {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+            workers.push(Worker::new(id, Arc::clone(&receiver), Arc::clone(&queued)));
+        }
+        Self {
+            workers,
+            sender,
+            queued,
+            depth,
         }
-        Self { workers, sender }
     }
 
-    pub fn execute<F>(&self, f: F)
-    where
-        F: FnOnce() + Send + 'static,
-    {
-        let job = Box::new(f);
-        self.sender.send(Message::NewJob(job)).unwrap();
+    /// Reserves a place in the job queue, or returns `None` if the queue is full
+    pub fn reserve(&self) -> Option<Permit<'_>> {
+        self.queued
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.depth).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| Permit { pool: self })
     }
 
     pub fn shutdown(&mut self) {
-        println!("Sending terminate message to all workers");
-        for _ in &self.workers {
+        let running = self.workers.iter().filter(|x| x.thread.is_some()).count();
+        if running == 0 {
+            return;
+        }
+        debug!("Sending terminate message to all workers");
+        for _ in 0..running {
             self.sender.send(Message::Terminate).unwrap();
         }
-        println!("Shutting down all workers");
+        debug!("Shutting down all workers");
         for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+            debug!("Shutting down worker {}", worker.id);
             if let Some(thread) = worker.thread.take() {
                 thread.join().unwrap();
             }
@@ -66,22 +85,48 @@ impl ThreadPool {
     }
 }
 
+impl Permit<'_> {
+    /// Queues a job in the reserved place
+    pub fn execute<F>(self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let job = Box::new(f);
+        // The place is now held by the queued job, and is released by the
+        // worker which picks it up
+        let pool = self.pool;
+        std::mem::forget(self);
+        pool.sender.send(Message::NewJob(job)).unwrap();
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.pool.queued.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
 pub struct Worker {
     id: usize,
     thread: Option<thread::JoinHandle<()>>,
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Self {
+    fn new(
+        id: usize,
+        receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
+        queued: Arc<AtomicUsize>,
+    ) -> Self {
         let thread = thread::spawn(move || loop {
             let message = receiver.lock().unwrap().recv().unwrap();
             match message {
                 Message::NewJob(job) => {
-                    println!("Worker {id} executing job.");
+                    queued.fetch_sub(1, Ordering::AcqRel);
+                    debug!("Worker {id} executing job.");
                     job();
                 }
                 Message::Terminate => {
-                    println!("Dropping worker {id}.");
+                    debug!("Dropping worker {id}.");
                     break;
                 }
             }
@@ -1,5 +1,3 @@
-use sysinfo::{System, SystemExt};
-
 pub struct Time {
     days: u64,
     hours: u64,
@@ -7,8 +5,8 @@ pub struct Time {
 }
 
 impl Time {
-    pub fn uptime(sys: &System) -> Self {
-        let uptime = sys.uptime();
+    /// Splits an uptime given in seconds
+    pub fn uptime(uptime: u64) -> Self {
         let days = uptime / 86400;
         let rem = uptime % 86400;
         let hours = rem / 3600;
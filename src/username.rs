@@ -0,0 +1,353 @@
+use {
+    crate::{config::Symlinks, log::warning},
+    std::{
+        ffi::CString,
+        fmt,
+        fs::{self, File},
+        io::{Error, ErrorKind},
+        os::unix::{
+            fs::OpenOptionsExt,
+            io::{AsRawFd, FromRawFd},
+        },
+        path::{Path, PathBuf},
+        str::FromStr,
+    },
+};
+
+/// Flags for opening users' files. A fifo or device must never block the
+/// worker, and reads from a regular file are unaffected by `O_NONBLOCK`.
+const OPEN_FLAGS: libc::c_int = libc::O_NONBLOCK | libc::O_NOCTTY;
+
+/// Opens `parts` one at a time relative to `dir`, refusing to follow a
+/// symlink at any step
+fn open_nofollow(dir: &Path, parts: &[&str]) -> Result<File, Error> {
+    let mut file = File::options()
+        .read(true)
+        .custom_flags(libc::O_DIRECTORY)
+        .open(dir)?;
+    for (i, part) in parts.iter().enumerate() {
+        let mut flags = libc::O_RDONLY | libc::O_CLOEXEC | libc::O_NOFOLLOW | OPEN_FLAGS;
+        if i + 1 < parts.len() {
+            flags |= libc::O_DIRECTORY;
+        }
+        let name = CString::new(*part)?;
+        let fd = unsafe { libc::openat(file.as_raw_fd(), name.as_ptr(), flags) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        file = unsafe { File::from_raw_fd(fd) };
+    }
+    Ok(file)
+}
+
+/// The path of the file which `file` refers to, as the kernel sees it
+fn opened_path(file: &File) -> Result<PathBuf, Error> {
+    fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd()))
+}
+
+/// The maximum length of a username, matching the `useradd` default on Linux
+pub const MAX_LEN: usize = 32;
+
+/// A username which has been checked to contain only characters which are safe
+/// to use as a single path component
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Username(String);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidUsername {
+    Empty,
+    TooLong,
+    /// Names may not begin with '.' or '-'
+    LeadingCharacter(char),
+    /// Names may only contain ascii letters, digits, '.', '_' and '-'
+    Character(char),
+}
+
+impl fmt::Display for InvalidUsername {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "username is empty"),
+            Self::TooLong => write!(f, "username is longer than {MAX_LEN} characters"),
+            Self::LeadingCharacter(c) => write!(f, "username may not begin with '{c}'"),
+            Self::Character(c) => {
+                write!(f, "invalid character '{}' in username", c.escape_default())
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidUsername {}
+
+impl FromStr for Username {
+    type Err = InvalidUsername;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(first) = s.chars().next() else {
+            return Err(InvalidUsername::Empty);
+        };
+        if s.len() > MAX_LEN {
+            return Err(InvalidUsername::TooLong);
+        }
+        if first == '.' || first == '-' {
+            return Err(InvalidUsername::LeadingCharacter(first));
+        }
+        if let Some(c) = s
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')))
+        {
+            return Err(InvalidUsername::Character(c));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl fmt::Display for Username {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&self.0)
+    }
+}
+
+impl AsRef<str> for Username {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Username {
+    /// Opens `file` inside of this user's directory under `root` for reading.
+    /// The symlink policy is enforced on the file which was actually opened,
+    /// so a link swapped in after the check cannot lead anywhere else. Returns
+    /// `None` if the file does not exist, is not a regular file, or if reaching
+    /// it would violate the symlink policy.
+    pub fn open(&self, root: &Path, file: &str, policy: Symlinks) -> Result<Option<File>, Error> {
+        match self.open_any(root, file, policy)? {
+            Some(opened) if opened.metadata()?.is_file() => Ok(Some(opened)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Lists the names in the directory `dir` inside of this user's directory
+    /// under `root`, which is reached under the same symlink policy as `open`.
+    /// Returns `None` if it does not exist or is not a directory. The listing
+    /// is read through `/proc`, which must be mounted.
+    pub fn read_dir(
+        &self,
+        root: &Path,
+        dir: &str,
+        policy: Symlinks,
+    ) -> Result<Option<Vec<String>>, Error> {
+        let opened = match self.open_any(root, dir, policy)? {
+            Some(opened) if opened.metadata()?.is_dir() => opened,
+            _ => return Ok(None),
+        };
+        let names = fs::read_dir(format!("/proc/self/fd/{}", opened.as_raw_fd()))?
+            .flatten()
+            .filter_map(|e| e.file_name().into_string().ok())
+            .collect();
+        Ok(Some(names))
+    }
+
+    /// Opens `file`, whatever type it is, enforcing the symlink policy
+    fn open_any(&self, root: &Path, file: &str, policy: Symlinks) -> Result<Option<File>, Error> {
+        let opened = if policy == Symlinks::Deny {
+            let parts = std::iter::once(self.0.as_str())
+                .chain(file.split('/'))
+                .collect::<Vec<_>>();
+            open_nofollow(root, &parts)
+        } else {
+            File::options()
+                .read(true)
+                .custom_flags(OPEN_FLAGS)
+                .open(root.join(&self.0).join(file))
+        };
+        let opened = match opened {
+            Ok(f) => f,
+            Err(e) if e.raw_os_error() == Some(libc::ELOOP) => {
+                warning!("Refusing to follow symlink to {}/{file}", self.0);
+                return Ok(None);
+            }
+            Err(e)
+                if e.kind() == ErrorKind::NotFound || e.raw_os_error() == Some(libc::ENOTDIR) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+        // Within a chroot, everything which can be opened is inside of the root
+        if policy == Symlinks::WithinRoot && root != Path::new("/") {
+            let path = opened_path(&opened)?;
+            if !path.starts_with(root.canonicalize()?) {
+                warning!(
+                    "Refusing to serve {}/{file}, which resolves outside of the server root",
+                    self.0
+                );
+                return Ok(None);
+            }
+        }
+        Ok(Some(opened))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::os::unix::fs::symlink};
+
+    fn name(s: &str) -> Username {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(name("jack").as_ref(), "jack");
+        assert_eq!(name("mary-jane_2.0").as_ref(), "mary-jane_2.0");
+        assert_eq!(name(&"a".repeat(MAX_LEN)).as_ref().len(), MAX_LEN);
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!("".parse::<Username>(), Err(InvalidUsername::Empty));
+        assert_eq!(
+            "a".repeat(MAX_LEN + 1).parse::<Username>(),
+            Err(InvalidUsername::TooLong)
+        );
+        assert_eq!(
+            "..".parse::<Username>(),
+            Err(InvalidUsername::LeadingCharacter('.'))
+        );
+        assert_eq!(
+            ".plan".parse::<Username>(),
+            Err(InvalidUsername::LeadingCharacter('.'))
+        );
+        assert_eq!(
+            "-rf".parse::<Username>(),
+            Err(InvalidUsername::LeadingCharacter('-'))
+        );
+        assert_eq!(
+            "jack/../jill".parse::<Username>(),
+            Err(InvalidUsername::Character('/'))
+        );
+        assert_eq!(
+            "jack\0".parse::<Username>(),
+            Err(InvalidUsername::Character('\0'))
+        );
+        assert_eq!(
+            "jöe".parse::<Username>(),
+            Err(InvalidUsername::Character('ö'))
+        );
+    }
+
+    /// A server root holding a user's files, with links which stay inside of
+    /// it and one which leads out of it
+    struct Root(PathBuf);
+
+    impl Root {
+        fn new(test: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("toe-{test}-{}", std::process::id()));
+            _ = fs::remove_dir_all(&dir);
+            let root = dir.join("root");
+            fs::create_dir_all(root.join("jack/.plan.d")).unwrap();
+            fs::create_dir_all(dir.join("outside")).unwrap();
+            fs::write(dir.join("outside/secret"), "secret").unwrap();
+            fs::write(root.join("jack/.plan"), "plan").unwrap();
+            fs::write(root.join("jack/.plan.d/b"), "").unwrap();
+            fs::write(root.join("jack/.plan.d/a"), "").unwrap();
+            symlink(".plan", root.join("jack/.project")).unwrap();
+            symlink("../../outside/secret", root.join("jack/.pubkey")).unwrap();
+            symlink("jack", root.join("linked")).unwrap();
+            Self(dir)
+        }
+
+        fn path(&self) -> PathBuf {
+            self.0.join("root")
+        }
+
+        fn read(&self, user: &str, file: &str, policy: Symlinks) -> Option<String> {
+            let mut file = name(user).open(&self.path(), file, policy).unwrap()?;
+            let mut contents = String::new();
+            std::io::Read::read_to_string(&mut file, &mut contents).unwrap();
+            Some(contents)
+        }
+    }
+
+    impl Drop for Root {
+        fn drop(&mut self) {
+            _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn follow() {
+        let root = Root::new("follow");
+        assert_eq!(
+            root.read("jack", ".plan", Symlinks::Follow).unwrap(),
+            "plan"
+        );
+        assert_eq!(
+            root.read("jack", ".project", Symlinks::Follow).unwrap(),
+            "plan"
+        );
+        assert_eq!(
+            root.read("jack", ".pubkey", Symlinks::Follow).unwrap(),
+            "secret"
+        );
+        assert_eq!(
+            root.read("linked", ".plan", Symlinks::Follow).unwrap(),
+            "plan"
+        );
+    }
+
+    #[test]
+    fn within_root() {
+        let root = Root::new("within-root");
+        assert_eq!(
+            root.read("jack", ".plan", Symlinks::WithinRoot).unwrap(),
+            "plan"
+        );
+        assert_eq!(
+            root.read("jack", ".project", Symlinks::WithinRoot).unwrap(),
+            "plan"
+        );
+        assert_eq!(root.read("jack", ".pubkey", Symlinks::WithinRoot), None);
+        assert_eq!(
+            root.read("linked", ".plan", Symlinks::WithinRoot).unwrap(),
+            "plan"
+        );
+    }
+
+    #[test]
+    fn deny() {
+        let root = Root::new("deny");
+        assert_eq!(root.read("jack", ".plan", Symlinks::Deny).unwrap(), "plan");
+        assert_eq!(root.read("jack", ".project", Symlinks::Deny), None);
+        assert_eq!(root.read("jack", ".pubkey", Symlinks::Deny), None);
+        assert_eq!(root.read("linked", ".plan", Symlinks::Deny), None);
+    }
+
+    #[test]
+    fn missing_and_not_files() {
+        let root = Root::new("missing");
+        for policy in [Symlinks::Follow, Symlinks::WithinRoot, Symlinks::Deny] {
+            assert_eq!(root.read("jack", ".nofile", policy), None);
+            assert_eq!(root.read("jill", ".plan", policy), None);
+            assert_eq!(root.read("jack", ".plan.d", policy), None);
+            assert_eq!(root.read("jack", ".plan/x", policy), None);
+        }
+    }
+
+    #[test]
+    fn read_dir() {
+        let root = Root::new("read-dir");
+        for policy in [Symlinks::Follow, Symlinks::WithinRoot, Symlinks::Deny] {
+            let mut names = name("jack")
+                .read_dir(&root.path(), ".plan.d", policy)
+                .unwrap()
+                .unwrap();
+            names.sort();
+            assert_eq!(names, ["a", "b"]);
+            let plan = name("jack").read_dir(&root.path(), ".plan", policy);
+            assert_eq!(plan.unwrap(), None);
+        }
+        let linked = name("linked").read_dir(&root.path(), ".plan.d", Symlinks::Deny);
+        assert_eq!(linked.unwrap(), None);
+    }
+}
@@ -0,0 +1,304 @@
+//! Parsing of the `utmp`, `wtmp` and `lastlog` login records written by glibc
+//! on 64 bit Linux. Records are read from copies of the files, which the
+//! administrator places inside of the server root.
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufReader, Error, ErrorKind, Read},
+    os::unix::fs::FileExt,
+    path::Path,
+};
+
+/// The size of a single `struct utmp`
+const UTMP_SIZE: usize = 384;
+
+/// The number of `wtmp` records read at once when searching it
+const WTMP_CHUNK: usize = 64;
+
+/// The size of a single `struct lastlog`
+const LASTLOG_SIZE: usize = 292;
+
+/// `ut_type` for a user's login session
+const USER_PROCESS: i16 = 7;
+
+/// `ut_type` for a session which has ended
+const DEAD_PROCESS: i16 = 8;
+
+/// A login session, taken from a `utmp` or `wtmp` record
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub user: String,
+    /// The terminal, such as `pts/0`
+    pub line: String,
+    /// The remote host the user logged in from, if any
+    pub host: String,
+    /// The time of the record, in seconds since the epoch
+    pub time: i64,
+}
+
+/// The last login of a single user, from either `wtmp` or `lastlog`
+pub enum LastLogin {
+    /// The user is still logged in
+    Active(Session),
+    /// The user has logged in before, but not since the session ended
+    Ended(Session),
+    Never,
+}
+
+/// Reads a NUL padded string field
+fn string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn i32_at(bytes: &[u8], offset: usize) -> i32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[offset..offset + 4]);
+    i32::from_ne_bytes(buf)
+}
+
+/// A single raw record, with its `ut_type`
+fn record(bytes: &[u8]) -> (i16, Session) {
+    let kind = i16::from_ne_bytes([bytes[0], bytes[1]]);
+    let session = Session {
+        user: string(&bytes[44..76]),
+        line: string(&bytes[8..40]),
+        host: string(&bytes[76..332]),
+        time: i64::from(i32_at(bytes, 340)),
+    };
+    (kind, session)
+}
+
+/// Reads the records of a `utmp` or `wtmp` file from start to end, one at a
+/// time
+fn for_each_record(path: &Path, mut f: impl FnMut(i16, Session)) -> Result<(), Error> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut buf = [0; UTMP_SIZE];
+    loop {
+        match file.read_exact(&mut buf) {
+            Ok(()) => {
+                let (kind, session) = record(&buf);
+                f(kind, session);
+            }
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads the sessions which are currently open from a `utmp` file
+pub fn sessions(path: &Path) -> Result<Vec<Session>, Error> {
+    let mut sessions = Vec::new();
+    for_each_record(path, |kind, s| {
+        if kind == USER_PROCESS && !s.user.is_empty() {
+            sessions.push(s);
+        }
+    })?;
+    Ok(sessions)
+}
+
+/// Finds the most recent login of `user` in a `wtmp` file, and whether that
+/// session is still open. The file is read backwards, `WTMP_CHUNK` records at
+/// a time, as the newest records are at the end.
+pub fn last_login(path: &Path, user: &str) -> Result<LastLogin, Error> {
+    let file = File::open(path)?;
+    let size = UTMP_SIZE as u64;
+    let mut end = file.metadata()?.len() / size * size;
+    let mut buf = vec![0; WTMP_CHUNK * UTMP_SIZE];
+    // Terminals on which a session ended after the records read so far
+    let mut ended = HashSet::new();
+    while end > 0 {
+        let start = end.saturating_sub(buf.len() as u64);
+        let chunk = &mut buf[..usize::try_from(end - start).unwrap_or(0)];
+        file.read_exact_at(chunk, start)?;
+        for bytes in chunk.chunks_exact(UTMP_SIZE).rev() {
+            match record(bytes) {
+                (USER_PROCESS, s) if s.user == user => {
+                    return if ended.contains(&s.line) {
+                        Ok(LastLogin::Ended(s))
+                    } else {
+                        Ok(LastLogin::Active(s))
+                    };
+                }
+                (DEAD_PROCESS, s) => {
+                    ended.insert(s.line);
+                }
+                _ => {}
+            }
+        }
+        end = start;
+    }
+    Ok(LastLogin::Never)
+}
+
+/// Reads the last login of the user with `uid` from a `lastlog` file, which is
+/// indexed by uid. Only that user's record is read.
+pub fn lastlog(path: &Path, user: &str, uid: u32) -> Result<LastLogin, Error> {
+    let file = File::open(path)?;
+    let mut bytes = [0; LASTLOG_SIZE];
+    match file.read_exact_at(&mut bytes, u64::from(uid) * LASTLOG_SIZE as u64) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(LastLogin::Never),
+        Err(e) => return Err(e),
+    }
+    let time = i64::from(i32_at(&bytes, 0));
+    if time == 0 {
+        return Ok(LastLogin::Never);
+    }
+    Ok(LastLogin::Ended(Session {
+        user: String::from(user),
+        line: string(&bytes[4..36]),
+        host: string(&bytes[36..292]),
+        time,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{fs, path::PathBuf},
+    };
+
+    /// A `struct utmp` laid out as glibc writes it on 64 bit Linux
+    fn utmp(kind: i16, line: &str, user: &str, host: &str, time: i32) -> Vec<u8> {
+        let mut bytes = vec![0; UTMP_SIZE];
+        bytes[0..2].copy_from_slice(&kind.to_ne_bytes());
+        // ut_pid, which is not read
+        bytes[4..8].copy_from_slice(&1234_i32.to_ne_bytes());
+        bytes[8..8 + line.len()].copy_from_slice(line.as_bytes());
+        bytes[44..44 + user.len()].copy_from_slice(user.as_bytes());
+        bytes[76..76 + host.len()].copy_from_slice(host.as_bytes());
+        // ut_tv.tv_sec, then tv_usec which is not read
+        bytes[340..344].copy_from_slice(&time.to_ne_bytes());
+        bytes[344..348].copy_from_slice(&999_i32.to_ne_bytes());
+        bytes
+    }
+
+    /// A `struct lastlog`
+    fn lastlog_record(time: i32, line: &str, host: &str) -> Vec<u8> {
+        let mut bytes = vec![0; LASTLOG_SIZE];
+        bytes[0..4].copy_from_slice(&time.to_ne_bytes());
+        bytes[4..4 + line.len()].copy_from_slice(line.as_bytes());
+        bytes[36..36 + host.len()].copy_from_slice(host.as_bytes());
+        bytes
+    }
+
+    struct Temp(PathBuf);
+
+    impl Temp {
+        fn new(name: &str, records: &[Vec<u8>]) -> Self {
+            let path = std::env::temp_dir().join(format!("toe-{name}-{}", std::process::id()));
+            fs::write(&path, records.concat()).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for Temp {
+        fn drop(&mut self) {
+            _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn open_sessions() {
+        let file = Temp::new(
+            "utmp",
+            &[
+                utmp(2, "~", "reboot", "6.1.0", 100),
+                utmp(USER_PROCESS, "pts/0", "jack", "example.com", 200),
+                utmp(DEAD_PROCESS, "pts/1", "", "", 300),
+                utmp(USER_PROCESS, "tty1", "jill", "", 400),
+            ],
+        );
+        let sessions = sessions(&file.0).unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].user, "jack");
+        assert_eq!(sessions[0].line, "pts/0");
+        assert_eq!(sessions[0].host, "example.com");
+        assert_eq!(sessions[0].time, 200);
+        assert_eq!(sessions[1].user, "jill");
+        assert_eq!(sessions[1].host, "");
+    }
+
+    #[test]
+    fn full_width_fields() {
+        let user = "u".repeat(32);
+        let file = Temp::new(
+            "utmp-wide",
+            &[utmp(
+                USER_PROCESS,
+                &"l".repeat(32),
+                &user,
+                &"h".repeat(256),
+                1,
+            )],
+        );
+        let sessions = sessions(&file.0).unwrap();
+        assert_eq!(sessions[0].user, user);
+        assert_eq!(sessions[0].line.len(), 32);
+        assert_eq!(sessions[0].host.len(), 256);
+    }
+
+    #[test]
+    fn last_logins() {
+        let mut records = vec![utmp(USER_PROCESS, "pts/0", "jack", "old.example", 10)];
+        // Enough records that the search crosses from one chunk to another
+        for i in 0..WTMP_CHUNK * 2 {
+            let time = 20 + i32::try_from(i).unwrap();
+            records.push(utmp(USER_PROCESS, "pts/5", "jill", "", time));
+        }
+        records.push(utmp(DEAD_PROCESS, "pts/0", "", "", 500));
+        records.push(utmp(USER_PROCESS, "pts/1", "mary", "", 600));
+        let file = Temp::new("wtmp", &records);
+        match last_login(&file.0, "jack").unwrap() {
+            LastLogin::Ended(s) => {
+                assert_eq!(s.host, "old.example");
+                assert_eq!(s.time, 10);
+            }
+            _ => panic!("jack's session has ended"),
+        }
+        match last_login(&file.0, "jill").unwrap() {
+            LastLogin::Active(s) => assert_eq!(s.time, 19 + i64::try_from(WTMP_CHUNK * 2).unwrap()),
+            _ => panic!("jill is logged in"),
+        }
+        assert!(matches!(
+            last_login(&file.0, "mary").unwrap(),
+            LastLogin::Active(_)
+        ));
+        assert!(matches!(
+            last_login(&file.0, "nobody").unwrap(),
+            LastLogin::Never
+        ));
+    }
+
+    #[test]
+    fn lastlog_by_uid() {
+        let file = Temp::new(
+            "lastlog",
+            &[
+                lastlog_record(0, "", ""),
+                lastlog_record(0, "", ""),
+                lastlog_record(700, "pts/3", "example.org"),
+            ],
+        );
+        match lastlog(&file.0, "jack", 2).unwrap() {
+            LastLogin::Ended(s) => {
+                assert_eq!(s.user, "jack");
+                assert_eq!(s.line, "pts/3");
+                assert_eq!(s.host, "example.org");
+                assert_eq!(s.time, 700);
+            }
+            _ => panic!("jack has logged in before"),
+        }
+        assert!(matches!(
+            lastlog(&file.0, "root", 0).unwrap(),
+            LastLogin::Never
+        ));
+        // Past the end of the file
+        assert!(matches!(
+            lastlog(&file.0, "jill", 1000).unwrap(),
+            LastLogin::Never
+        ));
+    }
+}